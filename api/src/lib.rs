@@ -3,7 +3,7 @@ use validator::Validate;
 
 #[derive(Debug, Validate, Default, Serialize, Deserialize)]
 pub struct SignUp {
-    #[validate(email(message = "email"))]
+    /// Parsed and normalized by the server, which may reject disposable addresses.
     pub email: String,
 }
 
@@ -16,3 +16,33 @@ pub struct SignUpAuthenticated {
     /// Manually validated
     pub password: String,
 }
+
+#[derive(Debug, Validate, Default, Serialize, Deserialize)]
+pub struct Login {
+    pub email: String,
+    pub password: String,
+    /// Keep the session beyond the browser session.
+    #[serde(default)]
+    pub remember_me: bool,
+}
+
+#[derive(Debug, Validate, Default, Serialize, Deserialize)]
+pub struct ChangeEmail {
+    /// Parsed and normalized by the server, which may reject disposable addresses.
+    pub email: String,
+}
+
+#[derive(Debug, Validate, Default, Serialize, Deserialize)]
+pub struct Invite {
+    /// Parsed and normalized by the server, which may reject disposable addresses.
+    pub email: String,
+    /// Invite as an organization admin.
+    #[serde(default)]
+    pub admin: bool,
+}
+
+#[derive(Debug, Validate, Default, Serialize, Deserialize)]
+pub struct CreateOrganization {
+    #[validate(length(min = 1, message = "name"))]
+    pub name: String,
+}
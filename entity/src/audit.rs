@@ -0,0 +1,22 @@
+use chrono::{DateTime, FixedOffset};
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "audit")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: Uuid,
+    /// The user the action was performed on.
+    pub user_id: Option<Uuid>,
+    /// The user who performed the action, `None` if the user was deleted.
+    pub actor_id: Option<Uuid>,
+    pub action: String,
+    pub details: Json,
+    pub date: DateTime<FixedOffset>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
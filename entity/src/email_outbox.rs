@@ -0,0 +1,58 @@
+use chrono::{DateTime, FixedOffset};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// An email waiting for delivery, or delivered.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "email_outbox")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    #[sea_orm(column_type = "Text")]
+    pub html: String,
+    #[sea_orm(column_type = "Text")]
+    pub text: String,
+    /// The attachments, referring to files of the assets directory.
+    pub attachments: Json,
+    /// Additional headers, as pairs of name and value.
+    pub headers: Json,
+    /// The mailing list this email is sent to, see `mailing_list`.
+    pub mailing_list_id: Option<Uuid>,
+    /// The recipient, set for mailing list emails.
+    pub user_id: Option<Uuid>,
+    pub status: Status,
+    /// The number of failed delivery attempts.
+    pub attempts: i32,
+    pub next_attempt_date: DateTime<FixedOffset>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub last_error: Option<String>,
+    pub creation_date: DateTime<FixedOffset>,
+    pub sent_date: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::None)")]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    #[sea_orm(string_value = "pending")]
+    Pending,
+    #[sea_orm(string_value = "sent")]
+    Sent,
+    /// Delivery failed too often and is not retried anymore.
+    #[sea_orm(string_value = "dead")]
+    Dead,
+    /// Not sent, because the recipient is on the suppression list.
+    #[sea_orm(string_value = "suppressed")]
+    Suppressed,
+    /// Not sent, because the recipient unsubscribed from the mailing list.
+    #[sea_orm(string_value = "unsubscribed")]
+    Unsubscribed,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
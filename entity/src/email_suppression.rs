@@ -0,0 +1,34 @@
+use chrono::{DateTime, FixedOffset};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// An address emails are not sent to anymore.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "email_suppression")]
+pub struct Model {
+    /// The address in lowercase.
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub email: String,
+    pub reason: Reason,
+    /// The explanation of the email service, for example the SMTP response of a bounce.
+    #[sea_orm(column_type = "Text", nullable)]
+    pub details: Option<String>,
+    pub creation_date: DateTime<FixedOffset>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::None)")]
+#[serde(rename_all = "kebab-case")]
+pub enum Reason {
+    /// Delivery failed permanently.
+    #[sea_orm(string_value = "bounce")]
+    Bounce,
+    /// The recipient marked an email as spam.
+    #[sea_orm(string_value = "complaint")]
+    Complaint,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,22 @@
+use chrono::{DateTime, FixedOffset};
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+/// An external identity linked to a user, for example from single sign-on.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "identity")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub provider: String,
+    /// The subject identifier at the provider.
+    pub subject: String,
+    pub email: String,
+    pub creation_date: DateTime<FixedOffset>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,28 @@
+use chrono::{DateTime, FixedOffset};
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+use crate::organization_member::Role;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "invitation")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub email: String,
+    pub role: Role,
+    /// The user who created the invitation.
+    pub invited_by: Uuid,
+    pub creation_date: DateTime<FixedOffset>,
+    /// The date the invitation email was sent the last time.
+    pub sent_date: DateTime<FixedOffset>,
+    pub expiration_date: DateTime<FixedOffset>,
+    pub accepted_date: Option<DateTime<FixedOffset>>,
+    pub revoked_date: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1 +1,13 @@
+pub mod audit;
+pub mod email_outbox;
+pub mod email_suppression;
+pub mod identity;
+pub mod invitation;
+pub mod mailing_list;
+pub mod mailing_list_subscription;
+pub mod organization;
+pub mod organization_member;
+pub mod session;
+pub mod session_device;
+pub mod throttle;
 pub mod user;
@@ -0,0 +1,22 @@
+use chrono::{DateTime, FixedOffset};
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+/// The preference of a user for a mailing list. Users without one are not subscribed.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "mailing_list_subscription")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub mailing_list_id: Uuid,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub user_id: Uuid,
+    /// When the user subscribed the last time.
+    pub creation_date: DateTime<FixedOffset>,
+    /// Set while the user is unsubscribed, kept as a record of the opt-out.
+    pub unsubscribe_date: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
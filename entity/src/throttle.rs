@@ -0,0 +1,17 @@
+use chrono::{DateTime, FixedOffset};
+use sea_orm::entity::prelude::*;
+
+/// An expiring counter, the database backend of the throttle.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "throttle")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub key: String,
+    pub value: i64,
+    pub expiry_date: DateTime<FixedOffset>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
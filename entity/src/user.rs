@@ -12,6 +12,12 @@ pub struct Model {
     pub email: String,
     pub creation_date: DateTime<FixedOffset>,
     pub password: String,
+    /// The date the account gets deleted, set when the user requested the deletion.
+    pub deletion_date: Option<DateTime<FixedOffset>>,
+    /// Admins are support staff, they can impersonate other users.
+    pub admin: bool,
+    /// The locale emails are sent in, for example `de` or `en-gb`.
+    pub locale: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -27,8 +27,12 @@ async fn main() -> Result<()> {
     .await?;
 
     let app = Router::new();
-    let app = stack_zero.install_routes(app).with_state(MyState {
-        sz: Arc::new(stack_zero),
+    let app = stack_zero.install_routes(app);
+
+    let stack_zero = Arc::new(stack_zero);
+
+    let app = app.with_state(MyState {
+        sz: stack_zero.clone(),
     });
 
     let addr = SocketAddr::from(([127, 0, 0, 1], 3030));
@@ -36,7 +40,5 @@ async fn main() -> Result<()> {
 
     let listener = TcpListener::bind(addr).await?;
 
-    axum::serve(listener, app).await?;
-
-    Ok(())
+    stack_zero.serve(listener, app).await
 }
@@ -2,6 +2,19 @@ pub use sea_orm_migration::prelude::*;
 
 mod m20240823_000001_create_user_table;
 mod m20240910_163755_add_user_password;
+mod m20241014_000001_create_organization_tables;
+mod m20241015_000001_create_identity_table;
+mod m20241016_000001_add_account_deletion_and_audit;
+mod m20241017_000001_add_user_admin;
+mod m20241018_000001_create_session_table;
+mod m20241019_000001_create_email_outbox_table;
+mod m20241020_000001_add_email_outbox_text;
+mod m20241021_000001_add_user_locale;
+mod m20241022_000001_add_email_outbox_attachments;
+mod m20241023_000001_create_email_suppression_table;
+mod m20241024_000001_create_mailing_list_tables;
+mod m20241025_000001_normalize_user_email;
+mod m20241026_000001_create_session_device_and_throttle_tables;
 
 pub struct Migrator;
 
@@ -11,6 +24,19 @@ impl MigratorTrait for Migrator {
         vec![
             Box::new(m20240823_000001_create_user_table::Migration),
             Box::new(m20240910_163755_add_user_password::Migration),
+            Box::new(m20241014_000001_create_organization_tables::Migration),
+            Box::new(m20241015_000001_create_identity_table::Migration),
+            Box::new(m20241016_000001_add_account_deletion_and_audit::Migration),
+            Box::new(m20241017_000001_add_user_admin::Migration),
+            Box::new(m20241018_000001_create_session_table::Migration),
+            Box::new(m20241019_000001_create_email_outbox_table::Migration),
+            Box::new(m20241020_000001_add_email_outbox_text::Migration),
+            Box::new(m20241021_000001_add_user_locale::Migration),
+            Box::new(m20241022_000001_add_email_outbox_attachments::Migration),
+            Box::new(m20241023_000001_create_email_suppression_table::Migration),
+            Box::new(m20241024_000001_create_mailing_list_tables::Migration),
+            Box::new(m20241025_000001_normalize_user_email::Migration),
+            Box::new(m20241026_000001_create_session_device_and_throttle_tables::Migration),
         ]
     }
 }
@@ -23,4 +49,134 @@ enum User {
     Email,
     CreationDate,
     Password,
+    DeletionDate,
+    Admin,
+    Locale,
+}
+
+#[derive(DeriveIden)]
+enum Organization {
+    Table,
+    Id,
+    Name,
+    CreationDate,
+}
+
+#[derive(DeriveIden)]
+enum OrganizationMember {
+    Table,
+    OrganizationId,
+    UserId,
+    Role,
+    CreationDate,
+}
+
+#[derive(DeriveIden)]
+enum Invitation {
+    Table,
+    Id,
+    OrganizationId,
+    Email,
+    Role,
+    InvitedBy,
+    CreationDate,
+    SentDate,
+    ExpirationDate,
+    AcceptedDate,
+    RevokedDate,
+}
+
+#[derive(DeriveIden)]
+enum Identity {
+    Table,
+    Id,
+    UserId,
+    Provider,
+    Subject,
+    Email,
+    CreationDate,
+}
+
+#[derive(DeriveIden)]
+enum Audit {
+    Table,
+    Id,
+    UserId,
+    ActorId,
+    Action,
+    Details,
+    Date,
+}
+
+#[derive(DeriveIden)]
+enum Session {
+    Table,
+    Id,
+    Data,
+    ExpiryDate,
+}
+
+#[derive(DeriveIden)]
+enum SessionDevice {
+    Table,
+    SessionId,
+    UserId,
+    Device,
+}
+
+#[derive(DeriveIden)]
+enum Throttle {
+    Table,
+    Key,
+    Value,
+    ExpiryDate,
+}
+
+#[derive(DeriveIden)]
+enum EmailOutbox {
+    Table,
+    Id,
+    From,
+    To,
+    Subject,
+    Html,
+    Text,
+    Attachments,
+    Headers,
+    MailingListId,
+    UserId,
+    Status,
+    Attempts,
+    NextAttemptDate,
+    LastError,
+    CreationDate,
+    SentDate,
+}
+
+#[derive(DeriveIden)]
+enum EmailSuppression {
+    Table,
+    Email,
+    Reason,
+    Details,
+    CreationDate,
+}
+
+#[derive(DeriveIden)]
+enum MailingList {
+    Table,
+    Id,
+    Name,
+    Title,
+    Description,
+    CreationDate,
+}
+
+#[derive(DeriveIden)]
+enum MailingListSubscription {
+    Table,
+    MailingListId,
+    UserId,
+    CreationDate,
+    UnsubscribeDate,
 }
@@ -0,0 +1,109 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::{Invitation, Organization, OrganizationMember, User};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Organization::Table)
+                    .if_not_exists()
+                    .col(uuid(Organization::Id).primary_key())
+                    .col(string(Organization::Name))
+                    .col(timestamp_with_time_zone(Organization::CreationDate))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(OrganizationMember::Table)
+                    .if_not_exists()
+                    .col(uuid(OrganizationMember::OrganizationId))
+                    .col(uuid(OrganizationMember::UserId))
+                    .col(string(OrganizationMember::Role))
+                    .col(timestamp_with_time_zone(OrganizationMember::CreationDate))
+                    .primary_key(
+                        Index::create()
+                            .col(OrganizationMember::OrganizationId)
+                            .col(OrganizationMember::UserId),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(
+                                OrganizationMember::Table,
+                                OrganizationMember::OrganizationId,
+                            )
+                            .to(Organization::Table, Organization::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(OrganizationMember::Table, OrganizationMember::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(Invitation::Table)
+                    .if_not_exists()
+                    .col(uuid(Invitation::Id).primary_key())
+                    .col(uuid(Invitation::OrganizationId))
+                    .col(string(Invitation::Email))
+                    .col(string(Invitation::Role))
+                    .col(uuid(Invitation::InvitedBy))
+                    .col(timestamp_with_time_zone(Invitation::CreationDate))
+                    .col(timestamp_with_time_zone(Invitation::SentDate))
+                    .col(timestamp_with_time_zone(Invitation::ExpirationDate))
+                    .col(timestamp_with_time_zone_null(Invitation::AcceptedDate))
+                    .col(timestamp_with_time_zone_null(Invitation::RevokedDate))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Invitation::Table, Invitation::OrganizationId)
+                            .to(Organization::Table, Organization::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Invitation::Table, Invitation::InvitedBy)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-invitation-email")
+                    .table(Invitation::Table)
+                    .col(Invitation::Email)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Invitation::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(OrganizationMember::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Organization::Table).to_owned())
+            .await
+    }
+}
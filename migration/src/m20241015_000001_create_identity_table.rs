@@ -0,0 +1,50 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::{Identity, User};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Identity::Table)
+                    .if_not_exists()
+                    .col(uuid(Identity::Id).primary_key())
+                    .col(uuid(Identity::UserId))
+                    .col(string(Identity::Provider))
+                    .col(string(Identity::Subject))
+                    .col(string(Identity::Email))
+                    .col(timestamp_with_time_zone(Identity::CreationDate))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Identity::Table, Identity::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-identity-provider-subject")
+                    .table(Identity::Table)
+                    .col(Identity::Provider)
+                    .col(Identity::Subject)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Identity::Table).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,72 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::{Audit, User};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .add_column(timestamp_with_time_zone_null(User::DeletionDate))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(Audit::Table)
+                    .if_not_exists()
+                    .col(uuid(Audit::Id).primary_key())
+                    .col(uuid_null(Audit::UserId))
+                    .col(uuid_null(Audit::ActorId))
+                    .col(string(Audit::Action))
+                    .col(json_binary(Audit::Details))
+                    .col(timestamp_with_time_zone(Audit::Date))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Audit::Table, Audit::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Audit::Table, Audit::ActorId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::SetNull),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-audit-user-id")
+                    .table(Audit::Table)
+                    .col(Audit::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Audit::Table).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .drop_column(User::DeletionDate)
+                    .to_owned(),
+            )
+            .await
+    }
+}
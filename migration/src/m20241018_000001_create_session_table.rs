@@ -0,0 +1,39 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::Session;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Session::Table)
+                    .if_not_exists()
+                    .col(string(Session::Id).primary_key())
+                    .col(json_binary(Session::Data))
+                    .col(timestamp_with_time_zone(Session::ExpiryDate))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-session-expiry-date")
+                    .table(Session::Table)
+                    .col(Session::ExpiryDate)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Session::Table).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,48 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::EmailOutbox;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(EmailOutbox::Table)
+                    .if_not_exists()
+                    .col(uuid(EmailOutbox::Id).primary_key())
+                    .col(string(EmailOutbox::From))
+                    .col(string(EmailOutbox::To))
+                    .col(string(EmailOutbox::Subject))
+                    .col(text(EmailOutbox::Html))
+                    .col(string(EmailOutbox::Status))
+                    .col(integer(EmailOutbox::Attempts).default(0))
+                    .col(timestamp_with_time_zone(EmailOutbox::NextAttemptDate))
+                    .col(text_null(EmailOutbox::LastError))
+                    .col(timestamp_with_time_zone(EmailOutbox::CreationDate))
+                    .col(timestamp_with_time_zone_null(EmailOutbox::SentDate))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-email-outbox-status-next-attempt-date")
+                    .table(EmailOutbox::Table)
+                    .col(EmailOutbox::Status)
+                    .col(EmailOutbox::NextAttemptDate)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(EmailOutbox::Table).to_owned())
+            .await
+    }
+}
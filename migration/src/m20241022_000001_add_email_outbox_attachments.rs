@@ -0,0 +1,31 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::EmailOutbox;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(EmailOutbox::Table)
+                    .add_column(json_binary(EmailOutbox::Attachments).default(Expr::cust("'[]'")))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(EmailOutbox::Table)
+                    .drop_column(EmailOutbox::Attachments)
+                    .to_owned(),
+            )
+            .await
+    }
+}
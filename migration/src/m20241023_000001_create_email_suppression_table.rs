@@ -0,0 +1,30 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::EmailSuppression;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(EmailSuppression::Table)
+                    .if_not_exists()
+                    .col(string(EmailSuppression::Email).primary_key())
+                    .col(string(EmailSuppression::Reason))
+                    .col(text_null(EmailSuppression::Details))
+                    .col(timestamp_with_time_zone(EmailSuppression::CreationDate))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(EmailSuppression::Table).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,101 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::{EmailOutbox, MailingList, MailingListSubscription, User};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(MailingList::Table)
+                    .if_not_exists()
+                    .col(uuid(MailingList::Id).primary_key())
+                    .col(string_uniq(MailingList::Name))
+                    .col(string(MailingList::Title))
+                    .col(text(MailingList::Description))
+                    .col(timestamp_with_time_zone(MailingList::CreationDate))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(MailingListSubscription::Table)
+                    .if_not_exists()
+                    .col(uuid(MailingListSubscription::MailingListId))
+                    .col(uuid(MailingListSubscription::UserId))
+                    .col(timestamp_with_time_zone(
+                        MailingListSubscription::CreationDate,
+                    ))
+                    .col(timestamp_with_time_zone_null(
+                        MailingListSubscription::UnsubscribeDate,
+                    ))
+                    .primary_key(
+                        Index::create()
+                            .col(MailingListSubscription::MailingListId)
+                            .col(MailingListSubscription::UserId),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(
+                                MailingListSubscription::Table,
+                                MailingListSubscription::MailingListId,
+                            )
+                            .to(MailingList::Table, MailingList::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(
+                                MailingListSubscription::Table,
+                                MailingListSubscription::UserId,
+                            )
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(EmailOutbox::Table)
+                    .add_column(json_binary(EmailOutbox::Headers).default(Expr::cust("'[]'")))
+                    .add_column(uuid_null(EmailOutbox::MailingListId))
+                    .add_column(uuid_null(EmailOutbox::UserId))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(EmailOutbox::Table)
+                    .drop_column(EmailOutbox::Headers)
+                    .drop_column(EmailOutbox::MailingListId)
+                    .drop_column(EmailOutbox::UserId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_table(
+                Table::drop()
+                    .table(MailingListSubscription::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(MailingList::Table).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,52 @@
+use sea_orm_migration::prelude::*;
+
+use crate::{Invitation, User};
+
+/// The name of the unique index on the lowercase addresses of users.
+const USER_EMAIL_INDEX: &str = "idx-user-email-lower";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    /// Lowercases the domains of the stored addresses, like addresses entering the system, and
+    /// makes addresses unique regardless of case.
+    ///
+    /// Fails if two users have addresses that differ only in case, these have to be merged by
+    /// hand first.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        for (table, column) in [
+            (User::Table.to_string(), User::Email.to_string()),
+            (Invitation::Table.to_string(), Invitation::Email.to_string()),
+        ] {
+            db.execute_unprepared(&format!(
+                r#"UPDATE "{table}" SET "{column}" =
+                    substring("{column}" from '^(.*)@[^@]*$') || '@' ||
+                    lower(substring("{column}" from '@([^@]*)$'))
+                    WHERE "{column}" LIKE '%@%'"#
+            ))
+            .await?;
+        }
+
+        db.execute_unprepared(&format!(
+            r#"CREATE UNIQUE INDEX IF NOT EXISTS "{USER_EMAIL_INDEX}" ON "{}" (LOWER("{}"))"#,
+            User::Table.to_string(),
+            User::Email.to_string(),
+        ))
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(USER_EMAIL_INDEX)
+                    .table(User::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
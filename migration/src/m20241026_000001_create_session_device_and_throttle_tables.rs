@@ -0,0 +1,71 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::{SessionDevice, Throttle, User};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SessionDevice::Table)
+                    .if_not_exists()
+                    .col(string(SessionDevice::SessionId).primary_key())
+                    .col(uuid(SessionDevice::UserId))
+                    .col(json_binary(SessionDevice::Device))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(SessionDevice::Table, SessionDevice::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-session-device-user-id")
+                    .table(SessionDevice::Table)
+                    .col(SessionDevice::UserId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(Throttle::Table)
+                    .if_not_exists()
+                    .col(string(Throttle::Key).primary_key())
+                    .col(big_integer(Throttle::Value))
+                    .col(timestamp_with_time_zone(Throttle::ExpiryDate))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-throttle-expiry-date")
+                    .table(Throttle::Table)
+                    .col(Throttle::ExpiryDate)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Throttle::Table).to_owned())
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(SessionDevice::Table).to_owned())
+            .await
+    }
+}
@@ -1,22 +1,60 @@
-use std::sync::Arc;
+use std::{net::IpAddr, sync::Arc};
 
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
-use css_inline::CSSInliner;
+use chrono::Utc;
+use entity::organization_member::Role;
+use sea_orm::{prelude::Uuid, TransactionTrait};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tower_sessions::Session;
 use utoipa::OpenApi;
 use validator::Validate;
 
-use crate::{email, AppError, StackZero};
+use crate::{
+    audit,
+    client_ip::ClientIp,
+    email,
+    locale::AcceptLanguage,
+    organization::{invitations, organizations},
+    session::index as sessions,
+    user::{
+        authenticated::{self, Authenticated, NotImpersonated},
+        deletion, email_change, export, impersonation, lockout, users,
+    },
+    AppError, StackZero,
+};
+
+/// The minimum length of passwords.
+const MIN_PASSWORD_LENGTH: usize = 8;
 
 #[derive(OpenApi)]
 #[openapi(
-    paths(sign_up),
+    paths(
+        sign_up,
+        complete_sign_up,
+        login,
+        change_email,
+        impersonate,
+        list_sessions,
+        revoke_session,
+        revoke_other_sessions,
+        export_account,
+        request_account_deletion,
+        cancel_account_deletion,
+        create_organization,
+        invite,
+        resend_invitation,
+        revoke_invitation,
+        list_emails,
+        retry_email,
+        email_preferences,
+        update_email_preference
+    ),
     tags(
         (name = "stack-zero", description = "Stack Zero API")
     )
@@ -37,31 +75,682 @@ pub struct Doc;
 
 pub async fn sign_up(
     State(state): State<Arc<StackZero>>,
+    ClientIp(ip): ClientIp,
+    accept_language: AcceptLanguage,
     Json(sign_up): Json<api::SignUp>,
 ) -> Result<Response, AppError> {
     sign_up.validate()?;
 
     println!("{sign_up:?}");
 
-    let verification_link = email::verification::link(&state.config.base_url, &sign_up.email)?;
+    let email = match state.email_config.accept_address(&sign_up.email) {
+        Ok(email) => email,
+        Err(e) => return Ok(invalid_email(e)),
+    };
 
-    let email = sign_up.email;
+    if let Some(response) = limit_email(&state, &email, ip).await? {
+        return Ok(response);
+    }
 
     let site = &state.config.base_url;
 
-    let email = state.render(
+    let verification_link = email::verification::link(site, &email)?;
+
+    let content = state.render_email(
         "emails/email_verification",
-        json! {{"site": site, "name": email, "email": email}},
+        state.email_locale(&accept_language).as_deref(),
+        json! {{"site": site, "name": email, "email": email, "link": verification_link}},
     )?;
 
-    // TODO: cache inlining?
-
-    let inliner = CSSInliner::default();
-    let email = inliner.inline(&email)?;
+    state.send_email(email.as_str(), content).await?;
 
     Ok((StatusCode::ACCEPTED, ()).into_response())
 }
 
+/// Completes the sign-up with the token from the verification email.
+#[utoipa::path(post, path = "/sign-up/complete")]
+pub async fn complete_sign_up(
+    State(state): State<Arc<StackZero>>,
+    session: Session,
+    accept_language: AcceptLanguage,
+    Json(sign_up): Json<api::SignUpAuthenticated>,
+) -> Result<Response, AppError> {
+    sign_up.validate()?;
+
+    if sign_up.password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Ok(response::error(
+            StatusCode::BAD_REQUEST,
+            "password",
+            &format!("Password must have at least {MIN_PASSWORD_LENGTH} characters"),
+        ));
+    }
+
+    let Ok(email) = email::verification::verify(&sign_up.token) else {
+        return Ok(response::error(
+            StatusCode::BAD_REQUEST,
+            "token",
+            "Verification link is invalid or expired",
+        ));
+    };
+
+    let connection = &state.db_connection;
+
+    if users::get_by_email(connection, &email).await?.is_some() {
+        return Ok(response::error(
+            StatusCode::CONFLICT,
+            "email",
+            "User is already registered",
+        ));
+    }
+
+    let now = Utc::now().into();
+    let user = users::create(
+        connection,
+        &sign_up.name,
+        &email,
+        users::AuthenticationMethod::Password(sign_up.password),
+        state.email_locale(&accept_language),
+        now,
+    )
+    .await?;
+
+    authenticated::login(&session, &state.session_config, user.id, false).await?;
+    invitations::accept_pending(&session, connection, &user, now).await;
+
+    Ok(response::success(StatusCode::CREATED, "Signed up"))
+}
+
+/// Logs in with email and password.
+///
+/// Failed attempts are throttled per account and IP address, `429 Too Many Requests` with a
+/// `Retry-After` header is returned while attempts are not allowed.
+#[utoipa::path(post, path = "/login")]
+pub async fn login(
+    State(state): State<Arc<StackZero>>,
+    ClientIp(ip): ClientIp,
+    session: Session,
+    Json(login): Json<api::Login>,
+) -> Result<Response, AppError> {
+    // Addresses that can't be parsed don't belong to any account.
+    let Ok(email) = login.email.parse::<email::Address>() else {
+        return Ok(invalid_credentials());
+    };
+
+    let attempt = lockout::Attempt::new(&state.throttle, &state.login_config, email.as_str(), ip);
+
+    if let Some(retry_after) = attempt.retry_after().await? {
+        return Ok(too_many_requests(retry_after));
+    }
+
+    let connection = &state.db_connection;
+
+    let user = users::get_by_email(connection, &email)
+        .await?
+        .filter(|user| users::verify_password(user, &login.password));
+
+    let Some(user) = user else {
+        let locked = attempt.failed().await?;
+        if locked {
+            if let Some(user) = users::get_by_email(connection, &email).await? {
+                let content = lockout::render_email(&state, &user)?;
+                state.send_email(&user.email, content).await?;
+            }
+        }
+        return Ok(invalid_credentials());
+    };
+
+    attempt.succeeded().await?;
+
+    authenticated::login(&session, &state.session_config, user.id, login.remember_me).await?;
+    invitations::accept_pending(&session, connection, &user, Utc::now().into()).await;
+
+    Ok(response::success(StatusCode::OK, "Logged in"))
+}
+
+/// Requests a change of the email address of the current user.
+///
+/// A verification link is sent to the new address, the address is changed after it was
+/// followed. Emails are rate limited per recipient and IP address.
+#[utoipa::path(post, path = "/email-change")]
+pub async fn change_email(
+    State(state): State<Arc<StackZero>>,
+    ClientIp(ip): ClientIp,
+    NotImpersonated(user): NotImpersonated,
+    Json(change): Json<api::ChangeEmail>,
+) -> Result<Response, AppError> {
+    change.validate()?;
+
+    let email = match state.email_config.accept_address(&change.email) {
+        Ok(email) => email,
+        Err(e) => return Ok(invalid_email(e)),
+    };
+
+    if email.as_str() == user.email {
+        return Ok(response::error(
+            StatusCode::BAD_REQUEST,
+            "email",
+            "This is already your email address",
+        ));
+    }
+
+    if let Some(response) = limit_email(&state, &email, ip).await? {
+        return Ok(response);
+    }
+
+    if email_change::address_taken(&state.db_connection, &user, &email).await? {
+        return Ok(response::error(
+            StatusCode::CONFLICT,
+            "email",
+            "The email address is already in use by another account",
+        ));
+    }
+
+    let (verification, notice) = email_change::render_emails(&state, &user, &email)?;
+
+    state.send_email(email.as_str(), verification).await?;
+    state.send_email(&user.email, notice).await?;
+
+    Ok(response::success(
+        StatusCode::ACCEPTED,
+        "Verification email sent",
+    ))
+}
+
+/// Starts impersonating a user. Only admins can impersonate.
+#[utoipa::path(
+    post,
+    path = "/users/{user}/impersonate",
+    params(("user" = String, Path, description = "User id")),
+)]
+pub async fn impersonate(
+    State(state): State<Arc<StackZero>>,
+    NotImpersonated(admin): NotImpersonated,
+    session: Session,
+    Path(user): Path<Uuid>,
+) -> Result<Response, AppError> {
+    if !admin.admin {
+        return Ok(admin_required());
+    }
+
+    let connection = &state.db_connection;
+
+    let Some(user) = users::get(connection, user).await? else {
+        return Ok(not_found());
+    };
+
+    impersonation::start(
+        &session,
+        connection,
+        &state.impersonation_config,
+        &admin,
+        &user,
+        Utc::now().into(),
+    )
+    .await?;
+
+    Ok(response::success(StatusCode::OK, "Impersonating"))
+}
+
+/// Lists the active sessions of the current user.
+#[utoipa::path(get, path = "/sessions")]
+pub async fn list_sessions(
+    State(state): State<Arc<StackZero>>,
+    Authenticated(user): Authenticated,
+    session: Session,
+) -> Result<Response, AppError> {
+    let current = session.id().map(|id| id.to_string());
+    let sessions = sessions::list(
+        &state.session_store,
+        &state.session_index,
+        user.id,
+        current.as_deref(),
+    )
+    .await?;
+
+    Ok(Json(sessions).into_response())
+}
+
+/// Revokes a session of the current user, which logs out the device using it.
+#[utoipa::path(delete, path = "/sessions/{session}")]
+pub async fn revoke_session(
+    State(state): State<Arc<StackZero>>,
+    NotImpersonated(user): NotImpersonated,
+    Path(session): Path<String>,
+) -> Result<Response, AppError> {
+    if !sessions::revoke(
+        &state.session_store,
+        &state.session_index,
+        user.id,
+        &session,
+    )
+    .await?
+    {
+        return Ok(not_found());
+    }
+
+    Ok(response::success(StatusCode::OK, "Session revoked"))
+}
+
+/// Revokes all sessions of the current user except the current one.
+#[utoipa::path(delete, path = "/sessions")]
+pub async fn revoke_other_sessions(
+    State(state): State<Arc<StackZero>>,
+    NotImpersonated(user): NotImpersonated,
+    session: Session,
+) -> Result<Response, AppError> {
+    let current = session.id().map(|id| id.to_string());
+    let revoked = sessions::revoke_others(
+        &state.session_store,
+        &state.session_index,
+        user.id,
+        current.as_deref(),
+    )
+    .await?;
+
+    Ok(response::success(
+        StatusCode::OK,
+        &format!("{revoked} sessions revoked"),
+    ))
+}
+
+/// Downloads a ZIP archive of all the data stored about the current user.
+#[utoipa::path(get, path = "/account/export")]
+pub async fn export_account(
+    State(state): State<Arc<StackZero>>,
+    NotImpersonated(user): NotImpersonated,
+    session: Session,
+) -> Result<Response, AppError> {
+    let connection = &state.db_connection;
+
+    audit::record(
+        connection,
+        Some(user.id),
+        Some(user.id),
+        "account.exported",
+        json! {{}},
+        Utc::now().into(),
+    )
+    .await?;
+
+    let current = session.id().map(|id| id.to_string());
+    let sessions = sessions::list(
+        &state.session_store,
+        &state.session_index,
+        user.id,
+        current.as_deref(),
+    )
+    .await?;
+    let export = export::collect(connection, &user, sessions).await?;
+    let zip = export::zip(&export)?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"account.zip\"",
+            ),
+        ],
+        zip,
+    )
+        .into_response())
+}
+
+/// Requests the deletion of the current user's account.
+///
+/// A confirmation link is sent by email. After confirmation, the account is deleted when the
+/// grace period elapsed. Emails are rate limited per recipient and IP address.
+#[utoipa::path(post, path = "/account/deletion")]
+pub async fn request_account_deletion(
+    State(state): State<Arc<StackZero>>,
+    ClientIp(ip): ClientIp,
+    NotImpersonated(user): NotImpersonated,
+) -> Result<Response, AppError> {
+    if let Some(response) = limit_email(&state, &user.email.parse()?, ip).await? {
+        return Ok(response);
+    }
+
+    let txn = state.db_connection.begin().await?;
+    audit::record(
+        &txn,
+        Some(user.id),
+        Some(user.id),
+        "account.deletion-requested",
+        json! {{}},
+        Utc::now().into(),
+    )
+    .await?;
+
+    let content = deletion::render_email(&state, &user)?;
+
+    state.queue_email(&txn, &user.email, content).await?;
+    txn.commit().await?;
+
+    Ok(response::success(
+        StatusCode::ACCEPTED,
+        "Confirmation email sent",
+    ))
+}
+
+/// Cancels a scheduled deletion of the current user's account.
+#[utoipa::path(delete, path = "/account/deletion")]
+pub async fn cancel_account_deletion(
+    State(state): State<Arc<StackZero>>,
+    NotImpersonated(user): NotImpersonated,
+) -> Result<Response, AppError> {
+    if user.deletion_date.is_none() {
+        return Ok(not_found());
+    }
+
+    deletion::cancel(&state.db_connection, user, Utc::now().into()).await?;
+
+    Ok(response::success(
+        StatusCode::OK,
+        "Account deletion canceled",
+    ))
+}
+
+#[derive(Debug, Serialize)]
+struct Created {
+    id: Uuid,
+}
+
+/// Creates an organization with the current user as its admin.
+#[utoipa::path(post, path = "/organizations")]
+pub async fn create_organization(
+    State(state): State<Arc<StackZero>>,
+    Authenticated(user): Authenticated,
+    Json(organization): Json<api::CreateOrganization>,
+) -> Result<Response, AppError> {
+    organization.validate()?;
+
+    let organization = organizations::create(
+        &state.db_connection,
+        &organization.name,
+        user.id,
+        Utc::now().into(),
+    )
+    .await?;
+
+    Ok(created(organization.id, "Organization created"))
+}
+
+/// Invites an email address to an organization. Only organization admins can invite.
+///
+/// Emails are rate limited per recipient and IP address.
+#[utoipa::path(
+    post,
+    path = "/organizations/{organization}/invitations",
+    params(("organization" = String, Path, description = "Organization id")),
+)]
+pub async fn invite(
+    State(state): State<Arc<StackZero>>,
+    ClientIp(ip): ClientIp,
+    NotImpersonated(user): NotImpersonated,
+    Path(organization): Path<Uuid>,
+    Json(invite): Json<api::Invite>,
+) -> Result<Response, AppError> {
+    invite.validate()?;
+
+    let email = match state.email_config.accept_address(&invite.email) {
+        Ok(email) => email,
+        Err(e) => return Ok(invalid_email(e)),
+    };
+
+    let connection = &state.db_connection;
+
+    if !organizations::is_admin(connection, organization, user.id).await? {
+        return Ok(forbidden());
+    }
+    if let Some(response) = limit_email(&state, &email, ip).await? {
+        return Ok(response);
+    }
+
+    let role = if invite.admin {
+        Role::Admin
+    } else {
+        Role::Member
+    };
+
+    let txn = connection.begin().await?;
+    let invitation =
+        invitations::create(&txn, organization, &email, role, user.id, Utc::now().into()).await?;
+
+    let content = invitations::render_email(&state, &invitation).await?;
+
+    state.queue_email(&txn, &invitation.email, content).await?;
+    txn.commit().await?;
+
+    Ok(created(invitation.id, "Invitation sent"))
+}
+
+/// Sends a pending or expired invitation again and extends its expiration.
+///
+/// Emails are rate limited per recipient and IP address.
+#[utoipa::path(
+    post,
+    path = "/invitations/{invitation}/resend",
+    params(("invitation" = String, Path, description = "Invitation id")),
+)]
+pub async fn resend_invitation(
+    State(state): State<Arc<StackZero>>,
+    ClientIp(ip): ClientIp,
+    NotImpersonated(user): NotImpersonated,
+    Path(invitation): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let connection = &state.db_connection;
+
+    let Some(invitation) = invitations::get(connection, invitation).await? else {
+        return Ok(not_found());
+    };
+    if !organizations::is_admin(connection, invitation.organization_id, user.id).await? {
+        return Ok(forbidden());
+    }
+    if let Some(response) = limit_email(&state, &invitation.email.parse()?, ip).await? {
+        return Ok(response);
+    }
+
+    let txn = connection.begin().await?;
+    let invitation = invitations::renew(&txn, invitation, Utc::now().into()).await?;
+
+    let content = invitations::render_email(&state, &invitation).await?;
+
+    state.queue_email(&txn, &invitation.email, content).await?;
+    txn.commit().await?;
+
+    Ok(response::success(StatusCode::OK, "Invitation sent"))
+}
+
+/// Revokes an invitation, links sent out can not be used anymore.
+#[utoipa::path(
+    delete,
+    path = "/invitations/{invitation}",
+    params(("invitation" = String, Path, description = "Invitation id")),
+)]
+pub async fn revoke_invitation(
+    State(state): State<Arc<StackZero>>,
+    NotImpersonated(user): NotImpersonated,
+    Path(invitation): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let connection = &state.db_connection;
+
+    let Some(invitation) = invitations::get(connection, invitation).await? else {
+        return Ok(not_found());
+    };
+    if !organizations::is_admin(connection, invitation.organization_id, user.id).await? {
+        return Ok(forbidden());
+    }
+
+    invitations::revoke(connection, invitation, Utc::now().into()).await?;
+
+    Ok(response::success(StatusCode::OK, "Invitation revoked"))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailsQuery {
+    status: Option<entity::email_outbox::Status>,
+}
+
+/// Lists the most recent emails of the outbox. Only admins can list emails.
+#[utoipa::path(
+    get,
+    path = "/admin/emails",
+    params(("status" = Option<String>, Query, description = "pending, sent, dead, suppressed, or unsubscribed")),
+)]
+pub async fn list_emails(
+    State(state): State<Arc<StackZero>>,
+    NotImpersonated(user): NotImpersonated,
+    Query(query): Query<EmailsQuery>,
+) -> Result<Response, AppError> {
+    if !user.admin {
+        return Ok(admin_required());
+    }
+
+    let emails = email::outbox::list(&state.db_connection, query.status).await?;
+
+    Ok(Json(emails).into_response())
+}
+
+/// Delivers a pending or dead email again, starting over with its attempts. Only admins can
+/// retry emails.
+#[utoipa::path(
+    post,
+    path = "/admin/emails/{email}/retry",
+    params(("email" = String, Path, description = "Email id")),
+)]
+pub async fn retry_email(
+    State(state): State<Arc<StackZero>>,
+    NotImpersonated(user): NotImpersonated,
+    Path(email): Path<Uuid>,
+) -> Result<Response, AppError> {
+    if !user.admin {
+        return Ok(admin_required());
+    }
+
+    if email::outbox::retry(&state.db_connection, email, Utc::now().into())
+        .await?
+        .is_none()
+    {
+        return Ok(not_found());
+    }
+
+    Ok(response::success(StatusCode::OK, "Email scheduled"))
+}
+
+/// Lists the mailing lists and whether the current user is subscribed to them.
+#[utoipa::path(get, path = "/email/preferences")]
+pub async fn email_preferences(
+    State(state): State<Arc<StackZero>>,
+    Authenticated(user): Authenticated,
+) -> Result<Response, AppError> {
+    let preferences = email::mailing_list::preferences(&state.db_connection, user.id).await?;
+
+    Ok(Json(preferences).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailPreference {
+    subscribed: bool,
+}
+
+/// Subscribes the current user to a mailing list or unsubscribes the user.
+#[utoipa::path(
+    put,
+    path = "/email/preferences/{list}",
+    params(("list" = String, Path, description = "Mailing list name")),
+)]
+pub async fn update_email_preference(
+    State(state): State<Arc<StackZero>>,
+    NotImpersonated(user): NotImpersonated,
+    Path(list): Path<String>,
+    Json(preference): Json<EmailPreference>,
+) -> Result<Response, AppError> {
+    let connection = &state.db_connection;
+    let Some(list) = email::mailing_list::find(connection, &list).await? else {
+        return Ok(not_found());
+    };
+
+    let now = Utc::now().into();
+    if preference.subscribed {
+        email::mailing_list::subscribe(connection, list.id, user.id, now).await?;
+        Ok(response::success(StatusCode::OK, "Subscribed"))
+    } else {
+        email::mailing_list::unsubscribe(connection, list.id, user.id, now).await?;
+        Ok(response::success(StatusCode::OK, "Unsubscribed"))
+    }
+}
+
+fn created(id: Uuid, message: &str) -> Response {
+    (
+        StatusCode::CREATED,
+        Json(ApiResponse::Success {
+            data: Some(Created { id }),
+            message: message.into(),
+        }),
+    )
+        .into_response()
+}
+
+/// Counts an email to `to` triggered from `ip`. Returns `429 Too Many Requests` with a
+/// `Retry-After` header if too many emails were sent to the address or triggered from the IP
+/// address, see [`email::rate_limit`].
+async fn limit_email(
+    state: &StackZero,
+    to: &email::Address,
+    ip: Option<IpAddr>,
+) -> anyhow::Result<Option<Response>> {
+    let retry_after =
+        email::rate_limit::retry_after(&state.throttle, &state.email_config.rate_limit, to, ip)
+            .await?;
+    Ok(retry_after.map(too_many_requests))
+}
+
+fn too_many_requests(retry_after: std::time::Duration) -> Response {
+    let mut response = response::error(
+        StatusCode::TOO_MANY_REQUESTS,
+        "too-many-requests",
+        "Too many attempts, try again later",
+    );
+    // Round up, so that clients don't retry too early.
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, seconds.into());
+    response
+}
+
+fn forbidden() -> Response {
+    response::error(
+        StatusCode::FORBIDDEN,
+        "forbidden",
+        "Organization admin permissions required",
+    )
+}
+
+fn invalid_email(e: anyhow::Error) -> Response {
+    response::error(StatusCode::BAD_REQUEST, "email", &e.to_string())
+}
+
+fn invalid_credentials() -> Response {
+    response::error(
+        StatusCode::UNAUTHORIZED,
+        "credentials",
+        "Invalid email or password",
+    )
+}
+
+fn admin_required() -> Response {
+    response::error(
+        StatusCode::FORBIDDEN,
+        "forbidden",
+        "Admin permissions required",
+    )
+}
+
+fn not_found() -> Response {
+    response::error(StatusCode::NOT_FOUND, "not-found", "Not found")
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(untagged)]
 enum ApiResponse<T: Serialize> {
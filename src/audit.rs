@@ -0,0 +1,47 @@
+//! The audit log of security relevant actions.
+
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset};
+use entity::audit;
+use sea_orm::{prelude::*, ConnectionTrait, QueryOrder};
+
+/// Record an action performed by `actor` on `user`.
+pub async fn record(
+    connection: &impl ConnectionTrait,
+    actor: Option<Uuid>,
+    user: Option<Uuid>,
+    action: &str,
+    details: Json,
+    date: DateTime<FixedOffset>,
+) -> Result<()> {
+    let entry = audit::Model {
+        id: Uuid::new_v4(),
+        user_id: user,
+        actor_id: actor,
+        action: action.into(),
+        details,
+        date,
+    };
+
+    audit::Entity::insert(audit::ActiveModel::from(entry))
+        .exec(connection)
+        .await?;
+
+    Ok(())
+}
+
+/// The entries about or performed by the user, oldest first.
+pub async fn of_user(
+    connection: &impl ConnectionTrait,
+    user_id: Uuid,
+) -> Result<Vec<audit::Model>> {
+    Ok(audit::Entity::find()
+        .filter(
+            audit::Column::UserId
+                .eq(user_id)
+                .or(audit::Column::ActorId.eq(user_id)),
+        )
+        .order_by_asc(audit::Column::Date)
+        .all(connection)
+        .await?)
+}
@@ -0,0 +1,154 @@
+//! Periodic background tasks that are stopped on shutdown.
+
+use std::{
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use futures_util::FutureExt;
+use tokio::{sync::watch, task::JoinHandle};
+
+#[derive(Debug)]
+pub struct BackgroundTasks {
+    shutdown: watch::Sender<bool>,
+    started: AtomicBool,
+    /// Each task returns whether one of its iterations panicked.
+    tasks: Mutex<Vec<(&'static str, JoinHandle<bool>)>>,
+}
+
+impl Default for BackgroundTasks {
+    fn default() -> Self {
+        Self {
+            shutdown: watch::Sender::new(false),
+            started: AtomicBool::new(false),
+            tasks: Default::default(),
+        }
+    }
+}
+
+impl BackgroundTasks {
+    /// Mark the tasks as started. Returns `false` if they already were.
+    pub fn mark_started(&self) -> bool {
+        !self.started.swap(true, Ordering::SeqCst)
+    }
+
+    /// Run `run` every `period`, starting now, until the tasks are stopped.
+    ///
+    /// A running iteration is completed before the task stops. A panicking iteration is logged
+    /// and the task continues with the next one.
+    pub fn spawn_periodic<F, Fut>(&self, name: &'static str, period: Duration, mut run: F)
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let mut shutdown = self.shutdown.subscribe();
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            let mut panicked = false;
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        if let Err(panic) = AssertUnwindSafe(async { run().await })
+                            .catch_unwind()
+                            .await
+                        {
+                            eprintln!("Background task {name} panicked: {}", message(&*panic));
+                            panicked = true;
+                        }
+                    }
+                    _ = shutdown.changed() => break,
+                }
+            }
+            panicked
+        });
+        self.tasks.lock().unwrap().push((name, task));
+    }
+
+    /// Stop all tasks and wait for them. Returns the names of the tasks that failed or
+    /// panicked in one of their iterations.
+    pub async fn stop(&self) -> Vec<&'static str> {
+        self.shutdown.send_replace(true);
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+
+        let mut failed = Vec::new();
+        for (name, task) in tasks {
+            match task.await {
+                Ok(false) => {}
+                Ok(true) => failed.push(name),
+                Err(e) => {
+                    eprintln!("Background task {name} failed: {e}");
+                    failed.push(name);
+                }
+            }
+        }
+        failed
+    }
+}
+
+/// The message of a panic payload.
+fn message(panic: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "<unknown>"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::BackgroundTasks;
+
+    #[tokio::test]
+    async fn stop_reports_failed_tasks() {
+        let tasks = BackgroundTasks::default();
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let counter = runs.clone();
+        tasks.spawn_periodic("counting", Duration::from_secs(60), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async {}
+        });
+        let failures = Arc::new(AtomicUsize::new(0));
+        let counter = failures.clone();
+        tasks.spawn_periodic("failing", Duration::from_millis(10), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async {
+                panic!("failure");
+            }
+        });
+
+        // The task keeps running after a panic.
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while failures.load(Ordering::SeqCst) < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(tasks.stop().await, vec!["failing"]);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn tasks_are_started_once() {
+        let tasks = BackgroundTasks::default();
+        assert!(tasks.mark_started());
+        assert!(!tasks.mark_started());
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap},
+};
+use ipnet::IpNet;
+use serde::Deserialize;
+
+/// The reverse proxies in front of the application.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Addresses and networks of the proxies whose `X-Forwarded-For` header is trusted.
+    pub trusted: Vec<IpNet>,
+}
+
+impl Config {
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted.iter().any(|net| net.contains(&ip))
+    }
+
+    /// The address of the client of a request from `peer`.
+    ///
+    /// `X-Forwarded-For` can be set by anyone, so it is only read if the peer is a trusted
+    /// proxy. Its entries are added by the proxies from left to right, the client is the last
+    /// one that is not a trusted proxy.
+    fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+
+        let forwarded_for: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+
+        let mut client = peer;
+        for entry in forwarded_for.into_iter().rev() {
+            let Ok(ip) = entry.trim().parse() else {
+                break;
+            };
+            client = ip;
+            if !self.is_trusted(ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// The IP address of the client.
+///
+/// Taken from the connection if the server was started with
+/// `into_make_service_with_connect_info::<SocketAddr>()`, or from `X-Forwarded-For` if the
+/// connection is from one of the trusted proxies of the [`Config`] in the request extensions.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(ConnectInfo(peer)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() else {
+            return Ok(Self(None));
+        };
+        let ip = match parts.extensions.get::<Arc<Config>>() {
+            Some(config) => config.client_ip(peer.ip(), &parts.headers),
+            None => peer.ip(),
+        };
+        Ok(Self(Some(ip)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use super::Config;
+
+    fn headers(forwarded_for: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_str(forwarded_for).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn forwarded_for_is_only_read_from_trusted_proxies() {
+        let config = Config {
+            trusted: vec![
+                "10.0.0.0/8".parse().unwrap(),
+                "192.0.2.1/32".parse().unwrap(),
+            ],
+        };
+        let headers = headers("203.0.113.9, 198.51.100.7, 192.0.2.1");
+
+        // A client setting the header itself.
+        let peer = "198.51.100.1".parse().unwrap();
+        assert_eq!(config.client_ip(peer, &headers), peer);
+
+        // The first address that is not a trusted proxy, from the right.
+        let peer = "10.1.2.3".parse().unwrap();
+        assert_eq!(
+            config.client_ip(peer, &headers),
+            "198.51.100.7".parse::<std::net::IpAddr>().unwrap()
+        );
+
+        // Without the header, the proxy is the client.
+        assert_eq!(config.client_ip(peer, &HeaderMap::new()), peer);
+
+        // Nothing is trusted by default.
+        let peer = "10.1.2.3".parse().unwrap();
+        assert_eq!(Config::default().client_ip(peer, &headers), peer);
+    }
+}
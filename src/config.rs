@@ -1,8 +1,25 @@
 use serde::Deserialize;
 
-use crate::email;
+use crate::{
+    client_ip, email, session,
+    user::{deletion, impersonation, lockout},
+};
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
-    pub smtp: email::Config,
+    pub smtp: Option<email::Config>,
+    #[serde(default)]
+    pub email: email::TransportConfig,
+    #[serde(default)]
+    pub login: lockout::Config,
+    #[serde(default)]
+    pub account: deletion::Config,
+    #[serde(default)]
+    pub impersonation: impersonation::Config,
+    #[serde(default)]
+    pub session: session::policy::Config,
+    #[serde(default)]
+    pub redis: session::redis::Config,
+    #[serde(default)]
+    pub proxies: client_ip::Config,
 }
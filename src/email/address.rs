@@ -0,0 +1,217 @@
+//! Email addresses entering the system.
+//!
+//! Addresses are parsed strictly as an RFC 5322 `addr-spec`, without display names or
+//! comments. The domain is normalized to lowercase ASCII (IDNA), the local part is kept as is.
+//! Stored addresses are compared case-insensitively, see [`crate::user::users::email_matches`].
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{bail, Error, Result};
+use serde::{Deserialize, Serialize};
+
+/// The maximum length of the local part, RFC 5321 4.5.3.1.1.
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+
+/// The maximum length of an address usable in a SMTP path, RFC 5321 4.5.3.1.3 and errata 1690.
+const MAX_ADDRESS_LENGTH: usize = 254;
+
+/// A valid and normalized email address.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Address(String);
+
+impl Address {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn local_part(&self) -> &str {
+        self.split().0
+    }
+
+    /// The domain in lowercase ASCII.
+    pub fn domain(&self) -> &str {
+        self.split().1
+    }
+
+    fn split(&self) -> (&str, &str) {
+        self.0.rsplit_once('@').expect("Addresses contain an @")
+    }
+
+    /// Is the domain, or a parent domain of it, one of `domains`?
+    pub fn is_in_domains(&self, domains: &[String]) -> bool {
+        let domain = self.domain();
+        domains.iter().any(|blocked| {
+            let blocked = blocked.trim().trim_end_matches('.').to_ascii_lowercase();
+            domain == blocked
+                || domain
+                    .strip_suffix(&blocked)
+                    .is_some_and(|sub| sub.ends_with('.'))
+        })
+    }
+}
+
+impl FromStr for Address {
+    type Err = Error;
+
+    fn from_str(address: &str) -> Result<Self> {
+        let address = address.trim();
+        let Some((local_part, domain)) = address.rsplit_once('@') else {
+            bail!("The email address is missing an @");
+        };
+
+        validate_local_part(local_part)?;
+        let domain = normalize_domain(domain)?;
+
+        let address = format!("{local_part}@{domain}");
+        if address.len() > MAX_ADDRESS_LENGTH {
+            bail!("The email address is too long");
+        }
+        Ok(Self(address))
+    }
+}
+
+impl TryFrom<String> for Address {
+    type Error = Error;
+
+    fn try_from(address: String) -> Result<Self> {
+        address.parse()
+    }
+}
+
+impl From<Address> for String {
+    fn from(address: Address) -> Self {
+        address.0
+    }
+}
+
+impl AsRef<str> for Address {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A `dot-atom` or a `quoted-string`, RFC 5322 3.4.1.
+fn validate_local_part(local_part: &str) -> Result<()> {
+    if local_part.is_empty() {
+        bail!("The email address is missing the part before the @");
+    }
+    if local_part.len() > MAX_LOCAL_PART_LENGTH {
+        bail!("The part before the @ is too long");
+    }
+
+    if let Some(quoted) = local_part
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    {
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            let valid = match c {
+                '\\' => chars.next().is_some_and(|c| c.is_ascii() && c != '\n'),
+                '"' => false,
+                c => c == ' ' || c.is_ascii_graphic(),
+            };
+            if !valid {
+                bail!("The email address contains invalid characters");
+            }
+        }
+        return Ok(());
+    }
+
+    if local_part.split('.').any(str::is_empty) {
+        bail!("The email address contains misplaced dots");
+    }
+    if !local_part.chars().all(|c| c == '.' || is_atext(c)) {
+        bail!("The email address contains invalid characters");
+    }
+    Ok(())
+}
+
+/// RFC 5322 3.2.3.
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c)
+}
+
+/// The domain in lowercase ASCII. Address literals are not supported.
+fn normalize_domain(domain: &str) -> Result<String> {
+    if domain.is_empty() {
+        bail!("The email address is missing the domain");
+    }
+    if domain.starts_with('[') {
+        bail!("IP addresses are not supported as email domains");
+    }
+
+    let Ok(domain) = idna::domain_to_ascii_strict(domain) else {
+        bail!("The domain of the email address is invalid");
+    };
+    if !domain.contains('.') || domain.ends_with('.') {
+        bail!("The domain of the email address is invalid");
+    }
+    Ok(domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Address;
+
+    fn parse(address: &str) -> Option<String> {
+        address.parse::<Address>().ok().map(String::from)
+    }
+
+    #[test]
+    fn addresses_are_normalized() {
+        assert_eq!(
+            parse(" John.Doe@Example.COM ").unwrap(),
+            "John.Doe@example.com"
+        );
+        assert_eq!(parse("john@Bücher.de").unwrap(), "john@xn--bcher-kva.de");
+        assert_eq!(
+            parse("\"john doe\"@example.com").unwrap(),
+            "\"john doe\"@example.com"
+        );
+        assert_eq!(
+            parse("a+tag@sub.example.com").unwrap(),
+            "a+tag@sub.example.com"
+        );
+    }
+
+    #[test]
+    fn invalid_addresses_are_rejected() {
+        for address in [
+            "",
+            "john",
+            "@example.com",
+            "john@",
+            "john@localhost",
+            "john@[127.0.0.1]",
+            "john..doe@example.com",
+            ".john@example.com",
+            "john doe@example.com",
+            "John <john@example.com>",
+            "john@exa mple.com",
+            "john@-example.com",
+            "\"jo\"hn\"@example.com",
+            // Delivering to internationalized local parts requires SMTPUTF8.
+            "jürgen@example.com",
+        ] {
+            assert_eq!(parse(address), None, "{address}");
+        }
+        let long = format!("{}@example.com", "a".repeat(65));
+        assert_eq!(parse(&long), None);
+    }
+
+    #[test]
+    fn domains_match_their_subdomains() {
+        let domains = vec!["Mailinator.com".to_string()];
+        let address = |a: &str| a.parse::<Address>().unwrap();
+        assert!(address("john@mailinator.com").is_in_domains(&domains));
+        assert!(address("john@eu.mailinator.com").is_in_domains(&domains));
+        assert!(!address("john@notmailinator.com").is_in_domains(&domains));
+    }
+}
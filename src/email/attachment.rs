@@ -0,0 +1,199 @@
+//! Files of the assets directory attached to emails.
+//!
+//! Images are embedded into the HTML body with the `cid` template function, for example
+//! `<img src="{{ cid(path="static/logo.png") }}">`. It refers to `assets/static/logo.png` by a
+//! `cid:` URL, and the image is attached to the email as an inline part when the template is
+//! rendered. Only calls of the function attach images, `cid:` URLs in the rendered data don't.
+//! Other files are attached with [`Content::attach`](super::Content::attach).
+//!
+//! Emails only refer to the files until they are delivered, so they must not change while
+//! emails are in the outbox.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use lettre::message::header::ContentType;
+use serde::{Deserialize, Serialize};
+use tera::Value;
+
+/// The domain of the content ids of embedded assets.
+const CONTENT_ID_DOMAIN: &str = "assets";
+
+/// Replaces `/` in content ids, which would be escaped by Tera.
+const PATH_SEPARATOR: char = '~';
+
+thread_local! {
+    /// The images embedded by the `cid` function, while collected by [`embedded_by`].
+    static EMBEDDED: RefCell<Option<Vec<Attachment>>> = const { RefCell::new(None) };
+}
+
+/// A file of the assets directory attached to an email.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attachment {
+    /// The path relative to the assets directory.
+    pub path: String,
+    /// The file name shown to the recipient.
+    pub filename: String,
+    pub content_type: String,
+    /// Set if the file is embedded into the HTML body and referenced by `cid:{content_id}`.
+    pub content_id: Option<String>,
+}
+
+impl Attachment {
+    /// Attach the file at `path` of the assets directory.
+    pub fn file(path: &str, content_type: &str) -> Result<Self> {
+        validate_path(path)?;
+        ContentType::parse(content_type)
+            .with_context(|| format!("Invalid content type: {content_type}"))?;
+        Ok(Self {
+            path: path.into(),
+            filename: filename(path).into(),
+            content_type: content_type.into(),
+            content_id: None,
+        })
+    }
+
+    /// Embed the image at `path` of the assets directory.
+    pub fn inline(path: &str) -> Result<Self> {
+        validate_path(path)?;
+        let Some(content_type) = image_content_type(path) else {
+            bail!("Unsupported image type: {path}");
+        };
+        Ok(Self {
+            path: path.into(),
+            filename: filename(path).into(),
+            content_type: content_type.into(),
+            content_id: Some(content_id(path)),
+        })
+    }
+
+    /// Read the file from the assets directory.
+    pub fn load(&self, assets: &Path) -> Result<Vec<u8>> {
+        let path = asset_path(assets, &self.path)?;
+        fs::read(&path).with_context(|| format!("Failed to read attachment {path:?}"))
+    }
+}
+
+/// Run `render` and return the images the `cid` function embedded while it ran.
+///
+/// Templates are rendered synchronously, so the images are collected per thread.
+pub fn embedded_by<T>(render: impl FnOnce() -> T) -> (T, Vec<Attachment>) {
+    let outer = EMBEDDED.replace(Some(Vec::new()));
+    let result = render();
+    let embedded = EMBEDDED.replace(outer).unwrap_or_default();
+    (result, embedded)
+}
+
+/// The `cid` template function, returns the `cid:` URL of the image at `path` and attaches the
+/// image, see [`embedded_by`].
+pub fn cid_function(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let Some(path) = args.get("path").and_then(Value::as_str) else {
+        return Err("`cid` requires a `path` argument".into());
+    };
+    let attachment = Attachment::inline(path).map_err(|e| tera::Error::msg(format!("{e:#}")))?;
+    let url = format!(
+        "cid:{}",
+        attachment
+            .content_id
+            .as_deref()
+            .expect("Inline attachments have a content id")
+    );
+    EMBEDDED.with_borrow_mut(|embedded| {
+        if let Some(embedded) = embedded {
+            if !embedded.contains(&attachment) {
+                embedded.push(attachment);
+            }
+        }
+    });
+    Ok(Value::String(url))
+}
+
+fn content_id(path: &str) -> String {
+    format!(
+        "{}@{CONTENT_ID_DOMAIN}",
+        path.replace('/', &PATH_SEPARATOR.to_string())
+    )
+}
+
+/// Paths are restricted to characters that are valid in content ids.
+fn validate_path(path: &str) -> Result<()> {
+    if path.is_empty() || path.starts_with('/') || path.ends_with('/') {
+        bail!("Invalid attachment path: {path}");
+    }
+    if !path.chars().all(is_path_char) {
+        bail!("Attachment paths may only contain letters, digits, `.`, `-`, `_` and `/`: {path}");
+    }
+    if path
+        .split('/')
+        .any(|segment| segment.is_empty() || segment == "..")
+    {
+        bail!("Attachment outside of the assets directory: {path}");
+    }
+    Ok(())
+}
+
+fn is_path_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "._-/".contains(c)
+}
+
+fn asset_path(assets: &Path, path: &str) -> Result<PathBuf> {
+    validate_path(path)?;
+    Ok(assets.join(path))
+}
+
+fn filename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn image_content_type(path: &str) -> Option<&'static str> {
+    let (_, extension) = path.rsplit_once('.')?;
+    Some(match extension.to_ascii_lowercase().as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tera::Value;
+
+    use super::{cid_function, embedded_by, Attachment};
+
+    #[test]
+    fn images_of_the_cid_function_are_embedded() {
+        let args = |path: &str| HashMap::from([("path".to_string(), Value::from(path))]);
+        let (url, attachments) = embedded_by(|| {
+            let url = cid_function(&args("static/logo.png")).unwrap();
+            cid_function(&args("static/logo.png")).unwrap();
+            url
+        });
+        assert_eq!(url, "cid:static~logo.png@assets");
+        assert_eq!(
+            attachments,
+            [Attachment::inline("static/logo.png").unwrap()]
+        );
+        assert_eq!(attachments[0].content_type, "image/png");
+        assert_eq!(attachments[0].filename, "logo.png");
+
+        // Outside of an email, nothing is collected.
+        cid_function(&args("static/logo.png")).unwrap();
+        let ((), attachments) = embedded_by(|| ());
+        assert!(attachments.is_empty());
+
+        assert!(Attachment::inline("static/logo.txt").is_err());
+        assert!(Attachment::file("../secret.pdf", "application/pdf").is_err());
+        assert!(Attachment::file("invoices/1 2.pdf", "application/pdf").is_err());
+        assert!(Attachment::file("invoices/1.pdf", "not a type").is_err());
+    }
+}
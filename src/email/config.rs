@@ -1,6 +1,7 @@
 use std::time::Duration;
 
 use anyhow::{anyhow, Result};
+use lettre::{transport::smtp::authentication::Credentials, AsyncSmtpTransport, Tokio1Executor};
 use serde::Deserialize;
 use url::Url;
 
@@ -76,6 +77,11 @@ impl Config {
             return Ok(from_address.clone());
         }
 
+        Self::default_from_address(site)
+    }
+
+    /// The noreply address of the site.
+    pub fn default_from_address(site: &Url) -> Result<String> {
         // Generate the noreply email address based on the site URL
         let host = site
             .host_str()
@@ -92,6 +98,36 @@ impl Config {
     }
 }
 
+impl EffectiveSmtp {
+    pub fn sender_address(&self) -> &str {
+        &self.from_address
+    }
+
+    /// The transport to the server, secured as configured.
+    pub fn transport(&self) -> Result<AsyncSmtpTransport<Tokio1Executor>> {
+        let builder = match self.credentials.security {
+            ConnectionSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&self.server)?,
+            ConnectionSecurity::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.server)?
+            }
+            ConnectionSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&self.server)
+            }
+        };
+
+        let mut builder = builder.port(self.port).timeout(Some(self.timeout));
+        let credentials = &self.credentials;
+        if !credentials.username.is_empty() {
+            builder = builder.credentials(Credentials::new(
+                credentials.username.clone(),
+                credentials.password.clone(),
+            ));
+        }
+
+        Ok(builder.build())
+    }
+}
+
 #[derive(Debug, Copy, Clone, Deserialize, Default)]
 #[serde(rename_all = "kebab-case")]
 enum ConnectionSecurity {
@@ -0,0 +1,378 @@
+//! CSS inlining of email templates.
+//!
+//! Most email clients ignore `<style>` and `<link>` tags, so the CSS of the email templates is
+//! inlined into `style` attributes once, when the templates are loaded. The CSS of a template
+//! consists of its own `<style>` tags and stylesheets, and the ones of the templates it extends.
+//! Stylesheets are resolved relative to the assets directory, for example
+//! `<link rel="stylesheet" href="/static/email.css">` refers to `assets/static/email.css`.
+//!
+//! Templates are inlined before Tera renders them, so CSS can only be applied to the elements
+//! the templates contain. Elements inserted by Tera expressions are not styled. Because the
+//! templates are parsed as HTML, Tera tags directly inside of `<table>`, `<tbody>` or `<tr>`
+//! elements would be moved in front of the table. The CSS of such templates, for example ones
+//! looping over table rows with `{% for %}`, is not inlined and a warning is returned.
+//!
+//! MJML templates are the exception, their `<mj-style>` tags are inlined after the templates
+//! are rendered and compiled to HTML, see [`super::mjml`]. The media queries of their columns
+//! are kept in a `<style>` tag.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use css_inline::{CSSInliner, InlineOptions};
+use tera::Tera;
+
+/// The prefix of the names of the email templates.
+const EMAIL_TEMPLATES: &str = "emails/";
+
+/// The element wrapping templates that are not complete HTML documents while they are inlined.
+const FRAGMENT: &str = "stack-zero-fragment";
+
+/// CSS features that are not, or only poorly, supported by email clients.
+const UNSUPPORTED: &[(&str, &str)] = &[
+    (
+        "@import",
+        "`@import` is not resolved, link the stylesheet instead",
+    ),
+    ("@media", "media queries can not be inlined and are dropped"),
+    (
+        "@font-face",
+        "web fonts are not supported by most email clients",
+    ),
+    (":hover", "pseudo-classes can not be inlined"),
+    (
+        "position:",
+        "`position` is not supported by most email clients",
+    ),
+    (
+        "display:flex",
+        "flexbox is not supported by most email clients",
+    ),
+    (
+        "display:grid",
+        "grid layout is not supported by most email clients",
+    ),
+    (
+        "var(",
+        "custom properties are not supported by most email clients",
+    ),
+    ("calc(", "`calc()` is not supported by most email clients"),
+];
+
+/// Inline the CSS of the email templates in `tera`. Returns warnings about unsupported CSS.
+pub fn precompile(tera: &mut Tera, assets: &Path) -> Result<Vec<String>> {
+    let mut warnings = Vec::new();
+    let mut inlined = Vec::new();
+
+    let names: Vec<String> = tera
+        .get_template_names()
+        .filter(|name| name.starts_with(EMAIL_TEMPLATES) && name.ends_with(".html"))
+        .map(Into::into)
+        .collect();
+
+    for name in names {
+        let template = tera.get_template(&name)?;
+        let own = source(tera, &name)?;
+
+        // MJML templates are inlined after they are rendered and compiled, see
+        // [`inline_document`].
+        if name.ends_with(super::mjml::EXTENSION) {
+            let own = own
+                .replace("<mj-style", "<style")
+                .replace("</mj-style>", "</style>");
+            warnings.extend(
+                self::warnings(&stylesheets(&own, assets)?)
+                    .into_iter()
+                    .map(|warning| format!("{name}: {warning}")),
+            );
+            continue;
+        }
+
+        // Styles of the most distant parent first, so that the template's own ones win.
+        let mut css = String::new();
+        for parent in template.parents.iter().rev() {
+            css.push_str(&stylesheets(&source(tera, parent)?, assets)?);
+        }
+        css.push_str(&stylesheets(&own, assets)?);
+
+        warnings.extend(
+            self::warnings(&css)
+                .into_iter()
+                .map(|warning| format!("{name}: {warning}")),
+        );
+
+        match inline(&own, &css).with_context(|| format!("Inlining CSS of `{name}`"))? {
+            Ok(html) => inlined.push((name, html)),
+            Err(tag) => warnings.push(format!(
+                "{name}: the Tera tag `{tag}` is directly inside of a table and would be moved \
+                 in front of it, the CSS of the template is not inlined"
+            )),
+        }
+    }
+
+    tera.add_raw_templates(inlined)?;
+    Ok(warnings)
+}
+
+fn source(tera: &Tera, name: &str) -> Result<String> {
+    let Some(path) = &tera.get_template(name)?.path else {
+        bail!("Template `{name}` was not loaded from a file");
+    };
+    fs::read_to_string(path).with_context(|| format!("Failed to read template `{path}`"))
+}
+
+/// Inline `css` into the template `source`. Fails with the Tera tag moved by the HTML parser,
+/// if there is one, see [`moved`].
+///
+/// Tera tags are replaced by placeholders while the HTML is parsed, so that they are not
+/// escaped.
+fn inline<'a>(source: &'a str, css: &str) -> Result<Result<String, &'a str>> {
+    let (html, tags) = protect(source);
+
+    let options = InlineOptions {
+        inline_style_tags: false,
+        load_remote_stylesheets: false,
+        ..Default::default()
+    };
+
+    let html = if html.contains("<html") {
+        CSSInliner::new(InlineOptions {
+            extra_css: Some(css.into()),
+            ..options
+        })
+        .inline(&html)?
+    } else {
+        // Only the first node of a fragment is serialized, so it's wrapped in a single element.
+        let wrapped = format!("<{FRAGMENT}>{html}</{FRAGMENT}>");
+        let html = CSSInliner::new(options).inline_fragment(&wrapped, css)?;
+        let start = html.find('>').map_or(0, |end| end + 1);
+        let end = html.rfind(&format!("</{FRAGMENT}>")).unwrap_or(html.len());
+        html[start..end].to_string()
+    };
+
+    if let Some(tag) = moved(&html, &tags) {
+        return Ok(Err(tag));
+    }
+    Ok(Ok(restore(&html, &tags)))
+}
+
+/// Inline the `<style>` tags of a rendered HTML document.
+pub fn inline_document(html: &str) -> Result<String> {
+    let options = InlineOptions {
+        load_remote_stylesheets: false,
+        ..Default::default()
+    };
+    Ok(CSSInliner::new(options).inline(html)?)
+}
+
+/// Replace the Tera tags by placeholders.
+fn protect(source: &str) -> (String, Vec<&str>) {
+    let mut html = String::with_capacity(source.len());
+    let mut tags = Vec::new();
+    let mut rest = source;
+
+    while let Some(start) = ["{{", "{%", "{#"]
+        .iter()
+        .filter_map(|open| rest.find(open))
+        .min()
+    {
+        let close = match &rest[start + 1..start + 2] {
+            "{" => "}}",
+            "%" => "%}",
+            _ => "#}",
+        };
+        let Some(end) = rest[start + 2..].find(close) else {
+            break;
+        };
+        let end = start + 2 + end + close.len();
+
+        html.push_str(&rest[..start]);
+        html.push_str(&placeholder(tags.len()));
+        tags.push(&rest[start..end]);
+        rest = &rest[end..];
+    }
+    html.push_str(rest);
+
+    (html, tags)
+}
+
+/// The first Tera tag whose placeholder is missing or out of order in `html`.
+///
+/// The HTML parser moves text that is directly inside of a table in front of it, which changes
+/// the order of the tags.
+fn moved<'a>(html: &str, tags: &[&'a str]) -> Option<&'a str> {
+    let mut position = 0;
+    for (i, tag) in tags.iter().enumerate() {
+        match html[position..].find(&placeholder(i)) {
+            Some(offset) => position += offset,
+            None => return Some(tag),
+        }
+    }
+    None
+}
+
+fn restore(html: &str, tags: &[&str]) -> String {
+    tags.iter()
+        .enumerate()
+        .fold(html.to_string(), |html, (i, tag)| {
+            html.replacen(&placeholder(i), tag, 1)
+        })
+}
+
+fn placeholder(index: usize) -> String {
+    format!("tera-tag-{index}-")
+}
+
+/// The CSS of the `<style>` tags and the linked stylesheets of the template.
+fn stylesheets(source: &str, assets: &Path) -> Result<String> {
+    let mut css = String::new();
+
+    let mut rest = source;
+    while let Some(start) = rest.find("<style") {
+        let Some(open_end) = rest[start..].find('>') else {
+            break;
+        };
+        let content = start + open_end + 1;
+        let Some(end) = rest[content..].find("</style>") else {
+            break;
+        };
+        css.push_str(&rest[content..content + end]);
+        css.push('\n');
+        rest = &rest[content + end..];
+    }
+
+    let mut rest = source;
+    while let Some(start) = rest.find("<link") {
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = &rest[start..start + end];
+        rest = &rest[start + end..];
+
+        if attribute(tag, "rel") != Some("stylesheet") {
+            continue;
+        }
+        let Some(href) = attribute(tag, "href") else {
+            continue;
+        };
+        let path = stylesheet_path(assets, href)?;
+        let stylesheet = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read stylesheet {path:?}"))?;
+        css.push_str(&stylesheet);
+        css.push('\n');
+    }
+
+    Ok(css)
+}
+
+fn stylesheet_path(assets: &Path, href: &str) -> Result<PathBuf> {
+    if href.contains("://") || href.starts_with("//") {
+        bail!("Only stylesheets in the assets directory can be inlined: {href}");
+    }
+    if href.split('/').any(|segment| segment == "..") {
+        bail!("Stylesheet outside of the assets directory: {href}");
+    }
+    Ok(assets.join(href.trim_start_matches('/')))
+}
+
+/// The value of the attribute `name` in `tag`.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {name}="))? + name.len() + 2;
+    let value = &tag[start..];
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let value = &value[1..];
+    Some(&value[..value.find(quote)?])
+}
+
+/// Warnings about CSS features email clients don't support.
+pub fn warnings(css: &str) -> Vec<String> {
+    let css: String = css
+        .to_lowercase()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+
+    UNSUPPORTED
+        .iter()
+        .filter(|(feature, _)| css.contains(feature))
+        .map(|(_, warning)| warning.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tera::{Context, Tera};
+
+    use super::{precompile, warnings};
+    use crate::test_helper::TempDir;
+
+    #[test]
+    fn templates_are_inlined_with_the_styles_of_their_parents() {
+        let dir = TempDir::new();
+        fs::create_dir_all(dir.join("emails")).unwrap();
+        fs::create_dir_all(dir.join("static")).unwrap();
+        fs::write(dir.join("static/email.css"), "p { color: red; }").unwrap();
+        fs::write(
+            dir.join("emails/base.html"),
+            r#"<html><head><link rel="stylesheet" href="/static/email.css"></head>
+            <body>{% block content %}{% endblock %}</body></html>"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("emails/hello.html"),
+            r#"{% extends "emails/base.html" %}{% block content %}
+            <style>b { font-weight: bold; }</style>
+            <p>Hello <b>{{ name }}</b>, {% if a > 1 %}a{% endif %}</p>{% endblock %}"#,
+        )
+        .unwrap();
+
+        let mut tera = Tera::new(dir.join("**/*.html").to_str().unwrap()).unwrap();
+        let warnings = precompile(&mut tera, &dir).unwrap();
+        drop(dir);
+
+        assert!(warnings.is_empty());
+        let context =
+            Context::from_serialize(serde_json::json! {{"name": "John", "a": 2}}).unwrap();
+        let html = tera.render("emails/hello.html", &context).unwrap();
+        assert!(html.contains(
+            r#"<p style="color: red;">Hello <b style="font-weight: bold;">John</b>, a</p>"#
+        ));
+        assert!(!html.contains("<link"));
+        assert!(!html.contains("<style"));
+    }
+
+    #[test]
+    fn templates_with_tags_directly_inside_of_tables_are_not_inlined() {
+        let dir = TempDir::new();
+        fs::create_dir_all(dir.join("emails")).unwrap();
+        fs::write(
+            dir.join("emails/rows.html"),
+            r#"<style>td { color: red; }</style>
+            <table><tbody>{% for row in rows %}<tr><td>{{ row }}</td></tr>{% endfor %}</tbody></table>"#,
+        )
+        .unwrap();
+
+        let mut tera = Tera::new(dir.join("**/*.html").to_str().unwrap()).unwrap();
+        let warnings = precompile(&mut tera, &dir).unwrap();
+        drop(dir);
+
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("emails/rows.html: the Tera tag `{% endfor %}`"));
+        let context = Context::from_serialize(serde_json::json! {{"rows": ["a", "b"]}}).unwrap();
+        let html = tera.render("emails/rows.html", &context).unwrap();
+        assert!(html.contains("<tbody><tr><td>a</td></tr><tr><td>b</td></tr></tbody>"));
+    }
+
+    #[test]
+    fn unsupported_css_is_reported() {
+        let warnings = warnings("div { display: flex; }\n@media (max-width: 600px) {}");
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("media queries"));
+        assert!(warnings[1].starts_with("flexbox"));
+    }
+}
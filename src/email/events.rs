@@ -0,0 +1,296 @@
+//! Delivery events posted by email services.
+//!
+//! Email services report bounces and spam complaints to `/api/email/events/{provider}`. Every
+//! provider has an [`EventParser`] that verifies the requests and extracts the addresses to
+//! suppress, see [`super::suppression`].
+
+use std::{collections::HashMap, fmt, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Utc};
+use entity::email_suppression::Reason;
+use p256::{
+    ecdsa::{signature::Verifier, Signature, VerifyingKey},
+    pkcs8::DecodePublicKey,
+};
+use serde::Deserialize;
+
+use super::suppression;
+use crate::{AppError, StackZero};
+
+/// An event that suppresses an address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub email: String,
+    pub reason: Reason,
+    pub details: Option<String>,
+}
+
+/// Verifies and parses the requests of an email service.
+pub trait EventParser: fmt::Debug + Send + Sync {
+    /// Fails if the request was not sent by the email service.
+    fn verify(&self, headers: &HeaderMap, body: &[u8]) -> Result<()>;
+
+    /// The events of the request that suppress an address, other events are skipped.
+    fn parse(&self, body: &[u8]) -> Result<Vec<Event>>;
+}
+
+/// The event parsers by provider name.
+pub type EventParsers = HashMap<&'static str, Arc<dyn EventParser>>;
+
+/// The `[email.sendgrid]` section.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SendGridConfig {
+    /// The base64 encoded public key of the signed event webhook.
+    pub verification_key: String,
+    /// Seconds the timestamp of a request may deviate from the current time. Older requests are
+    /// rejected, so that recorded requests can't be replayed.
+    #[serde(default = "default_timestamp_tolerance")]
+    pub timestamp_tolerance: u64,
+}
+
+fn default_timestamp_tolerance() -> u64 {
+    5 * 60
+}
+
+impl SendGridConfig {
+    pub fn parser(&self) -> Result<SendGrid> {
+        let key = BASE64
+            .decode(self.verification_key.trim())
+            .context("The SendGrid verification key is not base64 encoded")?;
+        let key = VerifyingKey::from_public_key_der(&key)
+            .map_err(|e| anyhow!("Invalid SendGrid verification key: {e}"))?;
+        Ok(SendGrid {
+            key,
+            timestamp_tolerance: Duration::from_secs(self.timestamp_tolerance),
+        })
+    }
+}
+
+/// SendGrid's signed event webhook.
+#[derive(Debug)]
+pub struct SendGrid {
+    key: VerifyingKey,
+    timestamp_tolerance: Duration,
+}
+
+impl SendGrid {
+    pub const SIGNATURE_HEADER: &'static str = "x-twilio-email-event-webhook-signature";
+    pub const TIMESTAMP_HEADER: &'static str = "x-twilio-email-event-webhook-timestamp";
+
+    /// Verify the request at the time `now`. The signature is an ECDSA P-256 signature of the
+    /// timestamp followed by the body.
+    fn verify_at(&self, headers: &HeaderMap, body: &[u8], now: DateTime<Utc>) -> Result<()> {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .with_context(|| format!("Missing {name} header"))
+        };
+        let signature = BASE64
+            .decode(header(Self::SIGNATURE_HEADER)?)
+            .context("The signature is not base64 encoded")?;
+        let signature = Signature::from_der(&signature).context("Invalid signature")?;
+
+        let timestamp = header(Self::TIMESTAMP_HEADER)?;
+        let seconds: i64 = timestamp.parse().context("Invalid timestamp")?;
+        let deviation = (now.timestamp() - seconds).unsigned_abs();
+        if deviation > self.timestamp_tolerance.as_secs() {
+            bail!("The timestamp deviates {deviation}s from the current time");
+        }
+
+        let mut payload = timestamp.as_bytes().to_vec();
+        payload.extend_from_slice(body);
+
+        self.key
+            .verify(&payload, &signature)
+            .context("The signature does not match")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SendGridEvent {
+    email: String,
+    event: String,
+    /// `bounce` or `blocked` for bounce events.
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    reason: Option<String>,
+}
+
+impl EventParser for SendGrid {
+    /// Requests with a timestamp outside of the tolerance are rejected, see
+    /// [`SendGridConfig::timestamp_tolerance`].
+    fn verify(&self, headers: &HeaderMap, body: &[u8]) -> Result<()> {
+        self.verify_at(headers, body, Utc::now())
+    }
+
+    fn parse(&self, body: &[u8]) -> Result<Vec<Event>> {
+        let events: Vec<SendGridEvent> = serde_json::from_slice(body)?;
+        Ok(events
+            .into_iter()
+            .filter_map(|event| {
+                let reason = match (event.event.as_str(), event.kind.as_deref()) {
+                    // Blocked messages are soft bounces, they may be delivered later.
+                    ("bounce", Some("blocked")) => return None,
+                    ("bounce", _) => Reason::Bounce,
+                    ("spamreport", _) => Reason::Complaint,
+                    _ => return None,
+                };
+                Some(Event {
+                    email: event.email,
+                    reason,
+                    details: event.reason,
+                })
+            })
+            .collect())
+    }
+}
+
+/// Receives the events of the email service `provider`.
+pub async fn webhook(
+    State(state): State<Arc<StackZero>>,
+    Path(provider): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, AppError> {
+    let Some(parser) = state.email_event_parsers.get(provider.as_str()) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    if let Err(e) = parser.verify(&headers, &body) {
+        eprintln!("Rejected {provider} email events: {e:#}");
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+    let events = match parser.parse(&body) {
+        Ok(events) => events,
+        Err(e) => {
+            eprintln!("Invalid {provider} email events: {e:#}");
+            return Ok(StatusCode::BAD_REQUEST.into_response());
+        }
+    };
+
+    let now = Utc::now().into();
+    for event in events {
+        suppression::suppress(
+            &state.db_connection,
+            &event.email,
+            event.reason,
+            event.details,
+            now,
+        )
+        .await?;
+    }
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// The parsers of the configured email services.
+pub fn parsers(sendgrid: Option<&SendGridConfig>) -> Result<EventParsers> {
+    let mut parsers = EventParsers::new();
+    if let Some(sendgrid) = sendgrid {
+        parsers.insert("sendgrid", Arc::new(sendgrid.parser()?));
+    }
+    Ok(parsers)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderMap;
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use chrono::{DateTime, Duration, Utc};
+    use entity::email_suppression::Reason;
+    use p256::{
+        ecdsa::{signature::Signer, Signature, SigningKey},
+        pkcs8::EncodePublicKey,
+    };
+
+    use super::{EventParser, SendGrid, SendGridConfig};
+
+    const EVENTS: &str = include_str!("fixtures/sendgrid_events.json");
+    const TIMESTAMP: &str = "1729500000";
+
+    fn sendgrid() -> (SendGrid, SigningKey) {
+        let signing_key = SigningKey::from_slice(&[7; 32]).unwrap();
+        let public_key = signing_key.verifying_key().to_public_key_der().unwrap();
+        let config = SendGridConfig {
+            verification_key: BASE64.encode(public_key.as_bytes()),
+            timestamp_tolerance: 300,
+        };
+        (config.parser().unwrap(), signing_key)
+    }
+
+    fn headers(signing_key: &SigningKey, body: &str) -> HeaderMap {
+        let signature: Signature = signing_key.sign(format!("{TIMESTAMP}{body}").as_bytes());
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            SendGrid::SIGNATURE_HEADER,
+            BASE64.encode(signature.to_der()).parse().unwrap(),
+        );
+        headers.insert(SendGrid::TIMESTAMP_HEADER, TIMESTAMP.parse().unwrap());
+        headers
+    }
+
+    fn sent_at() -> DateTime<Utc> {
+        DateTime::from_timestamp(TIMESTAMP.parse().unwrap(), 0).unwrap()
+    }
+
+    #[test]
+    fn signed_events_are_verified() {
+        let (sendgrid, signing_key) = sendgrid();
+        let headers = headers(&signing_key, EVENTS);
+        let now = sent_at() + Duration::seconds(10);
+
+        assert!(sendgrid.verify_at(&headers, EVENTS.as_bytes(), now).is_ok());
+        let tampered = EVENTS.replace("john@doe.com", "jane@doe.com");
+        assert!(sendgrid
+            .verify_at(&headers, tampered.as_bytes(), now)
+            .is_err());
+        assert!(sendgrid
+            .verify_at(&HeaderMap::new(), EVENTS.as_bytes(), now)
+            .is_err());
+    }
+
+    #[test]
+    fn replayed_events_are_rejected() {
+        let (sendgrid, signing_key) = sendgrid();
+        let headers = headers(&signing_key, EVENTS);
+        let verify = |now| sendgrid.verify_at(&headers, EVENTS.as_bytes(), now);
+
+        assert!(verify(sent_at() + Duration::minutes(5)).is_ok());
+        assert!(verify(sent_at() - Duration::minutes(5)).is_ok());
+        assert!(verify(sent_at() + Duration::minutes(6)).is_err());
+        assert!(verify(sent_at() - Duration::minutes(6)).is_err());
+        // The recorded request, long after it was sent.
+        assert!(sendgrid.verify(&headers, EVENTS.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn bounces_and_complaints_are_parsed() {
+        let (sendgrid, _) = sendgrid();
+        let events = sendgrid.parse(EVENTS.as_bytes()).unwrap();
+
+        let reasons: Vec<_> = events
+            .iter()
+            .map(|event| (event.email.as_str(), event.reason))
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                ("john@doe.com", Reason::Bounce),
+                ("jane@doe.com", Reason::Complaint)
+            ]
+        );
+        assert_eq!(
+            events[0].details.as_deref(),
+            Some("550 5.1.1 The email account does not exist.")
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! Writing emails to `.eml` files instead of delivering them, for development.
+
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::{Context, Result};
+use axum::async_trait;
+use chrono::Utc;
+use sea_orm::prelude::Uuid;
+
+use super::{dkim::Dkim, Email, Mailer};
+
+#[derive(Debug, Clone)]
+pub struct FileMailer {
+    directory: PathBuf,
+    dkim: Option<Arc<Dkim>>,
+}
+
+impl FileMailer {
+    pub fn new(directory: PathBuf, dkim: Option<Arc<Dkim>>) -> Self {
+        Self { directory, dkim }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: &Email) -> Result<()> {
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .with_context(|| format!("Creating email directory {:?}", self.directory))?;
+
+        // Sorted by the time they were sent.
+        let name = format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%dT%H%M%S%.3fZ"),
+            Uuid::new_v4()
+        );
+        let path = self.directory.join(name);
+        tokio::fs::write(
+            &path,
+            email.signed_message(self.dkim.as_deref())?.formatted(),
+        )
+        .await
+        .with_context(|| format!("Writing email to {path:?}"))?;
+
+        println!("Email to {} written to {path:?}", email.to);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::FileMailer;
+    use crate::{
+        email::{Content, Email, Mailer},
+        test_helper::TempDir,
+    };
+
+    #[tokio::test]
+    async fn writes_eml_files() {
+        let directory = TempDir::new();
+        let mailer = FileMailer::new(directory.to_path_buf(), None);
+        let from = "noreply@example.com".parse().unwrap();
+        let email = Email::new(
+            from,
+            "john@doe.com",
+            Content::from_html("Hello", "<p>Hello</p>".into()),
+            Path::new("assets"),
+        )
+        .unwrap();
+
+        mailer.send(&email).await.unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let content = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(content.contains("To: john@doe.com\r\n"));
+        assert!(content.contains("<p>Hello</p>"));
+    }
+}
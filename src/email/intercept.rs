@@ -0,0 +1,176 @@
+//! Redirecting all emails outside of production.
+//!
+//! Intercepted emails are delivered to a configured address instead of their recipients, so
+//! that staging and development never email real customers. The original recipient is kept in
+//! the `X-Original-To` header and shown in a banner on top of the body.
+
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use axum::async_trait;
+use lettre::message::Mailbox;
+use serde::Deserialize;
+
+use super::{Address, Email, Mailer, MemoryMailer};
+use crate::Environment;
+
+/// The header containing the original recipient.
+pub const ORIGINAL_TO_HEADER: &str = "X-Original-To";
+
+/// The `[email.intercept]` section.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Defaults to intercepting outside of production.
+    pub enabled: Option<bool>,
+    /// The address emails are delivered to, the from address if not set.
+    pub address: Option<String>,
+    /// Deliver to a `+tag` alias of the address that identifies the original recipient, for
+    /// example `dev+john=example.com@example.com`.
+    pub tag: bool,
+}
+
+impl Config {
+    pub fn is_enabled(&self, environment: Environment) -> bool {
+        self.enabled
+            .unwrap_or(environment != Environment::Production)
+    }
+}
+
+/// A mailer delivering all emails to one address.
+#[derive(Debug)]
+pub struct Intercept {
+    mailer: Arc<dyn Mailer>,
+    address: Address,
+    tag: bool,
+}
+
+impl Intercept {
+    /// Intercept the emails of `mailer`. `from` is used if no address is configured.
+    pub fn new(mailer: Arc<dyn Mailer>, config: &Config, from: &Mailbox) -> Result<Self> {
+        let address = config
+            .address
+            .clone()
+            .unwrap_or_else(|| from.email.to_string());
+        let address = address
+            .parse()
+            .with_context(|| format!("Invalid intercept address: {address}"))?;
+        Ok(Self {
+            mailer,
+            address,
+            tag: config.tag,
+        })
+    }
+
+    /// The email redirected to the intercept address.
+    pub fn intercept(&self, email: &Email) -> Result<Email> {
+        let original = email.to.email.to_string();
+
+        let address = if self.tag {
+            tagged(&self.address, &original)
+        } else {
+            self.address.to_string()
+        };
+
+        let mut email = email.clone();
+        email.to = Mailbox::new(email.to.name.clone(), address.parse()?);
+        email
+            .headers
+            .push((ORIGINAL_TO_HEADER.into(), original.clone()));
+        email.html = insert_banner(&email.html, &original);
+        email.text = format!("[Intercepted email to {original}]\n\n{}", email.text);
+        Ok(email)
+    }
+}
+
+#[async_trait]
+impl Mailer for Intercept {
+    async fn send(&self, email: &Email) -> Result<()> {
+        self.mailer.send(&self.intercept(email)?).await
+    }
+
+    fn captured(&self) -> Option<&MemoryMailer> {
+        self.mailer.captured()
+    }
+}
+
+/// The `+tag` alias of `address` for `original`.
+fn tagged(address: &Address, original: &str) -> String {
+    let tag: String = original
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            '@' => '=',
+            c if c.is_ascii_alphanumeric() || ".-_".contains(c) => c,
+            _ => '-',
+        })
+        .collect();
+    let local_part = address.local_part();
+    // The local part must not exceed 64 characters.
+    let length = 64usize.saturating_sub(local_part.len() + 1);
+    let tag = tag[..tag.len().min(length)].trim_matches('.');
+    format!("{local_part}+{tag}@{}", address.domain())
+}
+
+fn insert_banner(html: &str, original: &str) -> String {
+    let original = original
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    let banner = format!(
+        "<div style=\"background-color: #fff3cd; border: 1px solid #ffe69c; color: #664d03; \
+         font-family: sans-serif; font-size: 14px; margin: 0 0 16px; padding: 8px 12px;\">\
+         Intercepted email to <strong>{original}</strong>, it was not delivered to the \
+         recipient.</div>"
+    );
+
+    let body = html
+        .find("<body")
+        .and_then(|start| html[start..].find('>').map(|end| start + end + 1));
+    match body {
+        Some(position) => format!("{}{banner}{}", &html[..position], &html[position..]),
+        None => format!("{banner}{html}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc};
+
+    use super::{Config, Intercept};
+    use crate::{
+        email::{Content, Email, MemoryMailer},
+        Environment,
+    };
+
+    #[test]
+    fn recipients_are_rewritten() {
+        let from = "noreply@example.com".parse().unwrap();
+        let config = Config {
+            address: Some("dev@example.com".into()),
+            tag: true,
+            ..Config::default()
+        };
+        assert!(config.is_enabled(Environment::Development));
+        assert!(!config.is_enabled(Environment::Production));
+
+        let intercept = Intercept::new(Arc::new(MemoryMailer::default()), &config, &from).unwrap();
+        let html = "<html><body class=\"x\"><p>Hello</p></body></html>";
+        let content = Content::from_html("Hello", html.into());
+        let email = Email::new(from, "John <john@doe.com>", content, Path::new("assets")).unwrap();
+
+        let intercepted = intercept.intercept(&email).unwrap();
+        assert_eq!(
+            intercepted.to.to_string(),
+            "John <dev+john=doe.com@example.com>"
+        );
+        assert!(intercepted.html.starts_with("<html><body class=\"x\"><div"));
+        assert!(intercepted.html.contains("<strong>john@doe.com</strong>"));
+        assert!(intercepted
+            .text
+            .starts_with("[Intercepted email to john@doe.com]"));
+
+        let message = String::from_utf8(intercepted.message().unwrap().formatted()).unwrap();
+        assert!(message.contains("X-Original-To: john@doe.com\r\n"));
+    }
+}
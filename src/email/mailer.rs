@@ -0,0 +1,313 @@
+//! Delivering emails with a configurable transport.
+//!
+//! Production uses SMTP. For development, emails can be written to `.eml` files, and tests
+//! capture them in memory.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Context, Result};
+use axum::async_trait;
+use lettre::{
+    message::{
+        self,
+        header::{ContentType, HeaderName, HeaderValue},
+        Mailbox, MultiPart, SinglePart,
+    },
+    Message,
+};
+use serde::Deserialize;
+use url::Url;
+
+use super::{
+    dkim, events, intercept, rate_limit, Address, Attachment, Content, FileMailer, MemoryMailer,
+    SmtpMailer,
+};
+use crate::Environment;
+
+/// An email ready to be delivered.
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub from: Mailbox,
+    pub to: Mailbox,
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+    /// The attachments with their contents.
+    pub attachments: Vec<(Attachment, Vec<u8>)>,
+    /// Additional header names and values.
+    pub headers: Vec<(String, String)>,
+}
+
+impl Email {
+    /// The email with the attachments of `content` read from the `assets` directory.
+    pub fn new(from: Mailbox, to: &str, content: Content, assets: &Path) -> Result<Self> {
+        let to = to
+            .parse()
+            .with_context(|| format!("Invalid recipient address: {to}"))?;
+        let attachments = content
+            .attachments
+            .into_iter()
+            .map(|attachment| {
+                let body = attachment.load(assets)?;
+                Ok((attachment, body))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            from,
+            to,
+            subject: content.subject,
+            html: content.html,
+            text: content.text,
+            attachments,
+            headers: Vec::new(),
+        })
+    }
+
+    /// The MIME message, a `multipart/alternative` of the plain-text and the HTML body.
+    ///
+    /// Embedded images are related to the HTML body, other attachments are added in a
+    /// `multipart/mixed`.
+    pub fn message(&self) -> Result<Message> {
+        let (inline, files): (Vec<_>, Vec<_>) = self
+            .attachments
+            .iter()
+            .partition(|(attachment, _)| attachment.content_id.is_some());
+
+        let mut body = if inline.is_empty() {
+            MultiPart::alternative_plain_html(self.text.clone(), self.html.clone())
+        } else {
+            let mut related = MultiPart::related().singlepart(SinglePart::html(self.html.clone()));
+            for (attachment, content) in inline {
+                related = related.singlepart(part(attachment, content)?);
+            }
+            MultiPart::alternative()
+                .singlepart(SinglePart::plain(self.text.clone()))
+                .multipart(related)
+        };
+
+        if !files.is_empty() {
+            let mut mixed = MultiPart::mixed().multipart(body);
+            for (attachment, content) in files {
+                mixed = mixed.singlepart(part(attachment, content)?);
+            }
+            body = mixed;
+        }
+
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .to(self.to.clone())
+            .subject(&self.subject);
+        for (name, value) in &self.headers {
+            let name = HeaderName::new_from_ascii(name.clone())
+                .with_context(|| format!("Invalid header name: {name}"))?;
+            builder = builder.raw_header(HeaderValue::new(name, value.clone()));
+        }
+
+        Ok(builder.multipart(body)?)
+    }
+
+    /// The MIME message, signed if `dkim` is given.
+    pub fn signed_message(&self, dkim: Option<&dkim::Dkim>) -> Result<Message> {
+        let mut message = self.message()?;
+        if let Some(dkim) = dkim {
+            dkim.sign(&mut message);
+        }
+        Ok(message)
+    }
+}
+
+#[async_trait]
+pub trait Mailer: fmt::Debug + Send + Sync {
+    async fn send(&self, email: &Email) -> Result<()>;
+
+    /// The captured emails, if this is the in-memory transport.
+    fn captured(&self) -> Option<&MemoryMailer> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    #[default]
+    Smtp,
+    File,
+    Memory,
+}
+
+/// The `[email]` section.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub transport: Transport,
+    /// The directory the file transport writes `.eml` files to.
+    pub directory: PathBuf,
+    /// The number of delivery attempts before an email is given up.
+    pub max_attempts: u32,
+    /// Seconds to wait before the first retry, doubled on every further failure.
+    pub retry_backoff: u64,
+    /// The maximum number of seconds between two attempts.
+    pub max_retry_backoff: u64,
+    /// Sign outgoing emails with DKIM.
+    pub dkim: Option<dkim::Config>,
+    /// Domains of disposable email services, addresses of them and their subdomains are
+    /// rejected.
+    pub disposable_domains: Vec<String>,
+    /// Limits of the emails sent on request.
+    pub rate_limit: rate_limit::Config,
+    /// Receive bounces and spam complaints from SendGrid.
+    pub sendgrid: Option<events::SendGridConfig>,
+    /// Redirect all emails, enabled by default outside of production.
+    pub intercept: intercept::Config,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            transport: Transport::default(),
+            directory: "emails".into(),
+            max_attempts: 8,
+            retry_backoff: 60,
+            max_retry_backoff: 6 * 60 * 60,
+            dkim: None,
+            disposable_domains: Vec::new(),
+            rate_limit: rate_limit::Config::default(),
+            sendgrid: None,
+            intercept: intercept::Config::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Parse an address entering the system, see [`Address`], and reject disposable domains.
+    pub fn accept_address(&self, address: &str) -> Result<Address> {
+        let address: Address = address.parse()?;
+        if address.is_in_domains(&self.disposable_domains) {
+            return Err(anyhow!("Disposable email addresses are not accepted"));
+        }
+        Ok(address)
+    }
+
+    /// Create the configured mailer and the address emails are sent from.
+    ///
+    /// The from address is taken from the `[smtp]` section if it exists. Emails are
+    /// intercepted if enabled for the `environment`, see [`intercept`].
+    pub fn mailer(
+        &self,
+        smtp: Option<&super::Config>,
+        site: &Url,
+        environment: Environment,
+    ) -> Result<(Arc<dyn Mailer>, Mailbox)> {
+        let dkim = self
+            .dkim
+            .as_ref()
+            .map(|dkim| dkim.signer().map(Arc::new))
+            .transpose()?;
+
+        let (mailer, from): (Arc<dyn Mailer>, _) = match (self.transport, smtp) {
+            (Transport::Smtp, Some(smtp)) => {
+                let smtp = smtp.clone().into_effective(site)?;
+                let from = smtp.sender_address().to_string();
+                (Arc::new(SmtpMailer::new(&smtp, dkim)?), from)
+            }
+            (Transport::Smtp, None) => {
+                return Err(anyhow!(
+                    "The SMTP email transport requires an [smtp] section"
+                ))
+            }
+            (Transport::File, smtp) => (
+                Arc::new(FileMailer::new(self.directory.clone(), dkim)),
+                from_address(smtp, site)?,
+            ),
+            (Transport::Memory, smtp) => {
+                (Arc::new(MemoryMailer::default()), from_address(smtp, site)?)
+            }
+        };
+
+        let from = from
+            .parse()
+            .with_context(|| format!("Invalid from address: {from}"))?;
+
+        if self.intercept.is_enabled(environment) {
+            let mailer = intercept::Intercept::new(mailer, &self.intercept, &from)?;
+            return Ok((Arc::new(mailer), from));
+        }
+        Ok((mailer, from))
+    }
+}
+
+fn part(attachment: &Attachment, content: &[u8]) -> Result<SinglePart> {
+    let content_type = ContentType::parse(&attachment.content_type)
+        .with_context(|| format!("Invalid content type: {}", attachment.content_type))?;
+    let builder = match &attachment.content_id {
+        Some(content_id) => message::Attachment::new_inline(content_id.clone()),
+        None => message::Attachment::new(attachment.filename.clone()),
+    };
+    Ok(builder.body(content.to_vec(), content_type))
+}
+
+fn from_address(smtp: Option<&super::Config>, site: &Url) -> Result<String> {
+    match smtp {
+        Some(smtp) => smtp.effective_from_address(site),
+        None => super::Config::default_from_address(site),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::Email;
+    use crate::{email::Content, test_helper::TempDir};
+
+    #[test]
+    fn invalid_recipients_are_rejected() {
+        let from = "noreply@example.com".parse().unwrap();
+        let content = Content::from_html("Hello", "Hello".into());
+        assert!(Email::new(from, "not an address", content, Path::new("assets")).is_err());
+    }
+
+    #[test]
+    fn messages_are_multipart_alternative() {
+        let from = "noreply@example.com".parse().unwrap();
+        let content = Content::from_html("Hello", "<p>Hello <b>John</b></p>".into());
+        let email = Email::new(from, "john@doe.com", content, Path::new("assets")).unwrap();
+
+        let message = String::from_utf8(email.message().unwrap().formatted()).unwrap();
+        assert!(message.contains("Content-Type: multipart/alternative"));
+        assert!(message.contains("Content-Type: text/plain; charset=utf-8"));
+        assert!(message.contains("Content-Type: text/html; charset=utf-8"));
+        assert!(message.contains("\r\n\r\nHello John\r\n"));
+    }
+
+    #[test]
+    fn images_are_related_to_the_html_body() {
+        let assets = TempDir::new();
+        fs::create_dir_all(assets.join("static")).unwrap();
+        fs::write(assets.join("static/logo.png"), b"png").unwrap();
+        fs::write(assets.join("static/invoice.pdf"), b"pdf").unwrap();
+
+        let html = r#"<img src="cid:static~logo.png@assets">"#;
+        let mut content = Content::from_html("Invoice", html.into())
+            .attach("static/invoice.pdf", "application/pdf")
+            .unwrap();
+        content
+            .attachments
+            .push(crate::email::Attachment::inline("static/logo.png").unwrap());
+        let from = "noreply@example.com".parse().unwrap();
+        let email = Email::new(from, "john@doe.com", content, &assets).unwrap();
+        drop(assets);
+
+        let message = String::from_utf8(email.message().unwrap().formatted()).unwrap();
+        let position = |part: &str| message.find(part).unwrap_or_else(|| panic!("{part}"));
+        assert!(position("multipart/mixed") < position("multipart/alternative"));
+        assert!(position("multipart/alternative") < position("multipart/related"));
+        assert!(position("multipart/related") < position("Content-Type: text/html"));
+        assert!(message.contains("Content-ID: <static~logo.png@assets>"));
+        assert!(message.contains("Content-Disposition: attachment; filename=\"invoice.pdf\""));
+    }
+}
@@ -0,0 +1,323 @@
+//! Mailing lists for emails that are not transactional, like product updates.
+//!
+//! Users subscribe to lists in their email preferences. Every list email carries a signed
+//! unsubscribe link in the `List-Unsubscribe` header, together with `List-Unsubscribe-Post`, so
+//! that email clients can offer one-click unsubscribes (RFC 8058). Templates receive the link as
+//! `unsubscribe_url`. The outbox skips list emails to users who unsubscribed after they were
+//! queued.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use chrono::{DateTime, FixedOffset, Utc};
+use entity::{mailing_list, mailing_list_subscription};
+use sea_orm::{prelude::*, sea_query::OnConflict, ConnectionTrait, QueryOrder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use url::Url;
+
+use crate::{token, AppError, StackZero};
+
+/// The path of the unsubscribe page and the one-click unsubscribe endpoint.
+pub const UNSUBSCRIBE_PATH: &str = "/email/unsubscribe";
+
+/// Unsubscribe links keep working for a year, so that older emails can still be unsubscribed
+/// from.
+const UNSUBSCRIBE_EXPIRATION: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Create a mailing list.
+pub async fn create(
+    connection: &impl ConnectionTrait,
+    name: &str,
+    title: &str,
+    description: &str,
+    date: DateTime<FixedOffset>,
+) -> Result<mailing_list::Model> {
+    let list = mailing_list::Model {
+        id: Uuid::new_v4(),
+        name: name.into(),
+        title: title.into(),
+        description: description.into(),
+        creation_date: date,
+    };
+    mailing_list::Entity::insert(mailing_list::ActiveModel::from(list.clone()))
+        .exec(connection)
+        .await?;
+    Ok(list)
+}
+
+/// The mailing list named `name`.
+pub async fn find(
+    connection: &impl ConnectionTrait,
+    name: &str,
+) -> Result<Option<mailing_list::Model>> {
+    Ok(mailing_list::Entity::find()
+        .filter(mailing_list::Column::Name.eq(name))
+        .one(connection)
+        .await?)
+}
+
+/// The subscription of `user` to `mailing_list`, if the user is subscribed.
+pub async fn subscription(
+    connection: &impl ConnectionTrait,
+    mailing_list: Uuid,
+    user: Uuid,
+) -> Result<Option<mailing_list_subscription::Model>> {
+    Ok(
+        mailing_list_subscription::Entity::find_by_id((mailing_list, user))
+            .filter(mailing_list_subscription::Column::UnsubscribeDate.is_null())
+            .one(connection)
+            .await?,
+    )
+}
+
+pub async fn is_subscribed(
+    connection: &impl ConnectionTrait,
+    mailing_list: Uuid,
+    user: Uuid,
+) -> Result<bool> {
+    Ok(subscription(connection, mailing_list, user)
+        .await?
+        .is_some())
+}
+
+/// Subscribe `user` to `mailing_list`, again if the user unsubscribed before.
+pub async fn subscribe(
+    connection: &impl ConnectionTrait,
+    mailing_list: Uuid,
+    user: Uuid,
+    date: DateTime<FixedOffset>,
+) -> Result<()> {
+    if is_subscribed(connection, mailing_list, user).await? {
+        return Ok(());
+    }
+
+    let subscription = mailing_list_subscription::Model {
+        mailing_list_id: mailing_list,
+        user_id: user,
+        creation_date: date,
+        unsubscribe_date: None,
+    };
+    mailing_list_subscription::Entity::insert(mailing_list_subscription::ActiveModel::from(
+        subscription,
+    ))
+    .on_conflict(
+        OnConflict::columns([
+            mailing_list_subscription::Column::MailingListId,
+            mailing_list_subscription::Column::UserId,
+        ])
+        .update_columns([
+            mailing_list_subscription::Column::CreationDate,
+            mailing_list_subscription::Column::UnsubscribeDate,
+        ])
+        .to_owned(),
+    )
+    .exec(connection)
+    .await?;
+
+    Ok(())
+}
+
+/// Unsubscribe `user` from `mailing_list`. Returns `false` if the user was not subscribed.
+pub async fn unsubscribe(
+    connection: &impl ConnectionTrait,
+    mailing_list: Uuid,
+    user: Uuid,
+    date: DateTime<FixedOffset>,
+) -> Result<bool> {
+    let result = mailing_list_subscription::Entity::update_many()
+        .col_expr(
+            mailing_list_subscription::Column::UnsubscribeDate,
+            Expr::value(date),
+        )
+        .filter(mailing_list_subscription::Column::MailingListId.eq(mailing_list))
+        .filter(mailing_list_subscription::Column::UserId.eq(user))
+        .filter(mailing_list_subscription::Column::UnsubscribeDate.is_null())
+        .exec(connection)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
+
+/// A mailing list and whether the user is subscribed to it.
+#[derive(Debug, Serialize)]
+pub struct Preference {
+    pub name: String,
+    pub title: String,
+    pub description: String,
+    pub subscribed: bool,
+}
+
+/// All mailing lists with the subscriptions of `user`.
+pub async fn preferences(connection: &impl ConnectionTrait, user: Uuid) -> Result<Vec<Preference>> {
+    let lists = mailing_list::Entity::find()
+        .order_by_asc(mailing_list::Column::Title)
+        .all(connection)
+        .await?;
+    let subscriptions = mailing_list_subscription::Entity::find()
+        .filter(mailing_list_subscription::Column::UserId.eq(user))
+        .filter(mailing_list_subscription::Column::UnsubscribeDate.is_null())
+        .all(connection)
+        .await?;
+
+    Ok(lists
+        .into_iter()
+        .map(|list| Preference {
+            subscribed: subscriptions
+                .iter()
+                .any(|subscription| subscription.mailing_list_id == list.id),
+            name: list.name,
+            title: list.title,
+            description: list.description,
+        })
+        .collect())
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Claims {
+    mailing_list: Uuid,
+    user: Uuid,
+}
+
+/// The signed link that unsubscribes `user` from `mailing_list`.
+pub fn unsubscribe_link(base_url: &Url, mailing_list: Uuid, user: Uuid) -> Result<Url> {
+    let jwt = token::sign(Claims { mailing_list, user }, UNSUBSCRIBE_EXPIRATION)?;
+    let mut link = base_url.join(UNSUBSCRIBE_PATH)?;
+    link.query_pairs_mut().append_pair("t", &jwt);
+    Ok(link)
+}
+
+/// The headers identifying the list and offering the one-click unsubscribe.
+pub fn headers(
+    mailing_list: &mailing_list::Model,
+    unsubscribe: &Url,
+    base_url: &Url,
+) -> Vec<(String, String)> {
+    let host = base_url.host_str().unwrap_or("localhost");
+    vec![
+        ("List-Id".into(), format!("<{}.{host}>", mailing_list.name)),
+        ("List-Unsubscribe".into(), format!("<{unsubscribe}>")),
+        (
+            "List-Unsubscribe-Post".into(),
+            "List-Unsubscribe=One-Click".into(),
+        ),
+    ]
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnsubscribeQuery {
+    t: String,
+}
+
+/// The page confirming the unsubscribe, linked from the emails.
+///
+/// Links are opened by mail scanners, so visiting the page doesn't unsubscribe.
+pub async fn page(
+    State(state): State<Arc<StackZero>>,
+    Query(query): Query<UnsubscribeQuery>,
+) -> Result<Response, AppError> {
+    let Ok(claims) = token::verify::<Claims>(&query.t) else {
+        return Ok(StatusCode::BAD_REQUEST.into_response());
+    };
+    render(&state, &query.t, &claims, false).await
+}
+
+/// Unsubscribes with the token of the link. Posted by email clients on a one-click
+/// unsubscribe, or by the form of the [page].
+pub async fn one_click(
+    State(state): State<Arc<StackZero>>,
+    Query(query): Query<UnsubscribeQuery>,
+) -> Result<Response, AppError> {
+    let Ok(claims) = token::verify::<Claims>(&query.t) else {
+        return Ok(StatusCode::BAD_REQUEST.into_response());
+    };
+    unsubscribe(
+        &state.db_connection,
+        claims.mailing_list,
+        claims.user,
+        Utc::now().into(),
+    )
+    .await?;
+    render(&state, &query.t, &claims, true).await
+}
+
+async fn render(
+    state: &StackZero,
+    token: &str,
+    claims: &Claims,
+    unsubscribed: bool,
+) -> Result<Response, AppError> {
+    let Some(list) = mailing_list::Entity::find_by_id(claims.mailing_list)
+        .one(&state.db_connection)
+        .await?
+    else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let page = state.render(
+        "email/unsubscribe",
+        json! {{
+            "path": UNSUBSCRIBE_PATH,
+            "token": token,
+            "title": list.title,
+            "unsubscribed": unsubscribed,
+        }},
+    )?;
+    Ok(Html(page).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use chrono::Utc;
+    use sea_orm::prelude::Uuid;
+    use url::Url;
+
+    use super::{headers, unsubscribe_link, Claims};
+    use crate::token;
+
+    #[test]
+    fn unsubscribe_links_are_signed_and_advertised_in_headers() {
+        env::set_var("JWT_SECRET", "c3RhY2stemVyby10ZXN0LXNlY3JldA==");
+
+        let base_url = Url::parse("https://example.com/").unwrap();
+        let list = entity::mailing_list::Model {
+            id: Uuid::new_v4(),
+            name: "product-updates".into(),
+            title: "Product updates".into(),
+            description: String::new(),
+            creation_date: Utc::now().into(),
+        };
+        let user = Uuid::new_v4();
+
+        let link = unsubscribe_link(&base_url, list.id, user).unwrap();
+        assert_eq!(link.path(), "/email/unsubscribe");
+        let (_, jwt) = link.query_pairs().find(|(name, _)| name == "t").unwrap();
+        assert_eq!(
+            token::verify::<Claims>(&jwt).unwrap(),
+            Claims {
+                mailing_list: list.id,
+                user
+            }
+        );
+
+        let headers = headers(&list, &link, &base_url);
+        assert_eq!(
+            headers[0],
+            ("List-Id".into(), "<product-updates.example.com>".into())
+        );
+        assert_eq!(headers[1].1, format!("<{link}>"));
+        assert_eq!(
+            headers[2],
+            (
+                "List-Unsubscribe-Post".into(),
+                "List-Unsubscribe=One-Click".into()
+            )
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Capturing emails in memory, for tests.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use axum::async_trait;
+use url::Url;
+
+use super::{Email, Mailer};
+
+#[derive(Debug, Clone, Default)]
+pub struct MemoryMailer {
+    sent: Arc<Mutex<Vec<Email>>>,
+}
+
+#[async_trait]
+impl Mailer for MemoryMailer {
+    async fn send(&self, email: &Email) -> Result<()> {
+        self.sent.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+
+    fn captured(&self) -> Option<&MemoryMailer> {
+        Some(self)
+    }
+}
+
+impl MemoryMailer {
+    /// All emails sent, oldest first.
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// The emails sent to `to`, oldest first.
+    pub fn sent_to(&self, to: &str) -> Vec<Email> {
+        self.sent
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|email| email.to.email.to_string().eq_ignore_ascii_case(to))
+            .cloned()
+            .collect()
+    }
+
+    /// The last email sent to `to`.
+    ///
+    /// # Panics
+    ///
+    /// If no email was sent to `to`.
+    pub fn expect_sent_to(&self, to: &str) -> Email {
+        match self.sent_to(to).pop() {
+            Some(email) => email,
+            None => panic!("No email was sent to {to}, sent: {:#?}", self.sent()),
+        }
+    }
+
+    pub fn clear(&self) {
+        self.sent.lock().unwrap().clear();
+    }
+}
+
+impl Email {
+    /// The absolute URLs linked in the HTML body.
+    pub fn links(&self) -> Vec<Url> {
+        self.html
+            .split("href=\"")
+            .skip(1)
+            .filter_map(|rest| rest.split_once('"'))
+            .filter_map(|(href, _)| href.replace("&amp;", "&").parse().ok())
+            .collect()
+    }
+
+    /// The first link to `path`.
+    pub fn link_to(&self, path: &str) -> Option<Url> {
+        self.links().into_iter().find(|link| link.path() == path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::MemoryMailer;
+    use crate::email::{Content, Email, Mailer};
+
+    #[tokio::test]
+    async fn captured_emails_can_be_inspected() {
+        let mailer = MemoryMailer::default();
+        let from = "noreply@example.com".parse().unwrap();
+        let html = r#"<a href="https://example.com/sign-up?t=a&amp;b=c">Verify</a>"#;
+        let email = Email::new(
+            from,
+            "John@Doe.com",
+            Content::from_html("Verify", html.into()),
+            Path::new("assets"),
+        )
+        .unwrap();
+
+        mailer.send(&email).await.unwrap();
+
+        let email = mailer.expect_sent_to("john@doe.com");
+        assert_eq!(
+            email.link_to("/sign-up").unwrap().as_str(),
+            "https://example.com/sign-up?t=a&b=c"
+        );
+        assert!(mailer.sent_to("jane@doe.com").is_empty());
+    }
+}
@@ -0,0 +1,803 @@
+//! Compiling a subset of MJML to email HTML.
+//!
+//! This is not a complete MJML implementation, only the commonly used elements are supported:
+//!
+//! - `<mj-head>` with `<mj-title>`, `<mj-preview>`, `<mj-style>`, `<mj-breakpoint>` and
+//!   `<mj-attributes>` containing `<mj-all>`, `<mj-class>` and defaults of elements.
+//! - `<mj-body>` with `<mj-wrapper>`, `<mj-section>`, `<mj-group>`, `<mj-column>`,
+//!   `<mj-text>`, `<mj-button>`, `<mj-image>`, `<mj-divider>`, `<mj-spacer>` and `<mj-raw>`.
+//!
+//! Other elements, like `<mj-hero>`, `<mj-social>`, `<mj-navbar>`, `<mj-table>` or
+//! `<mj-font>`, are rejected. Like in MJML, columns take the full width on small screens and
+//! get their width from media queries above the breakpoint, columns in a group don't stack.
+
+use std::{collections::HashMap, fmt::Write};
+
+use anyhow::{anyhow, bail, Result};
+
+/// The extension of MJML email templates.
+pub const EXTENSION: &str = ".mjml.html";
+
+/// The default width of the body in pixels.
+const BODY_WIDTH: u32 = 600;
+
+/// The default screen width above which columns are shown side by side.
+const BREAKPOINT: &str = "480px";
+
+const FONT_FAMILY: &str = "Ubuntu, Helvetica, Arial, sans-serif";
+
+/// Elements whose content is HTML and not parsed as MJML.
+const ENDING_TAGS: &[&str] = &[
+    "mj-text",
+    "mj-button",
+    "mj-raw",
+    "mj-title",
+    "mj-preview",
+    "mj-style",
+];
+
+#[derive(Debug)]
+struct Element {
+    tag: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+    /// The HTML content of ending tags.
+    content: String,
+    line: usize,
+}
+
+impl Element {
+    fn attribute<'a>(&'a self, name: &str, default: &'a str) -> &'a str {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map_or(default, |(_, value)| value)
+    }
+
+    fn unsupported(&self) -> anyhow::Error {
+        anyhow!(
+            "Unsupported MJML element `<{}>` on line {}",
+            self.tag,
+            self.line
+        )
+    }
+}
+
+/// The default attributes of `<mj-attributes>`.
+#[derive(Debug, Default)]
+struct Defaults {
+    classes: HashMap<String, Vec<(String, String)>>,
+    tags: HashMap<String, Vec<(String, String)>>,
+    all: Vec<(String, String)>,
+}
+
+impl Defaults {
+    fn add(&mut self, attributes: &Element) -> Result<()> {
+        for child in &attributes.children {
+            match child.tag.as_str() {
+                "mj-all" => self.all.extend(child.attributes.iter().cloned()),
+                "mj-class" => {
+                    let mut attributes = child.attributes.clone();
+                    let Some(name) = attributes.iter().position(|(name, _)| name == "name") else {
+                        bail!("`<mj-class>` on line {} is missing `name`", child.line);
+                    };
+                    let (_, name) = attributes.remove(name);
+                    self.classes.entry(name).or_default().extend(attributes);
+                }
+                tag => self
+                    .tags
+                    .entry(tag.into())
+                    .or_default()
+                    .extend(child.attributes.iter().cloned()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the defaults to the attributes of `element` and its children. Their own attributes
+    /// come first, followed by the ones of their `mj-class`, their tag and `<mj-all>`.
+    fn apply(&self, element: &mut Element) {
+        let mut defaults = Vec::new();
+        for class in element.attribute("mj-class", "").split_whitespace() {
+            defaults.extend(self.classes.get(class).into_iter().flatten().cloned());
+        }
+        defaults.extend(self.tags.get(&element.tag).into_iter().flatten().cloned());
+        defaults.extend(self.all.iter().cloned());
+        element.attributes.extend(defaults);
+
+        for child in &mut element.children {
+            self.apply(child);
+        }
+    }
+}
+
+/// The HTML of the body.
+#[derive(Debug, Default)]
+struct Body {
+    html: String,
+    /// The classes and widths of the columns, for the media queries.
+    columns: Vec<(String, String)>,
+}
+
+/// The width of a column or group.
+struct Width {
+    pixels: u32,
+    /// The CSS width above the breakpoint.
+    css: String,
+    /// The class setting the width in the media queries.
+    class: String,
+}
+
+/// Compile an MJML document to HTML.
+pub fn compile(mjml: &str) -> Result<String> {
+    let root = Parser::new(mjml).document()?;
+    if root.tag != "mjml" {
+        bail!(
+            "The document must start with `<mjml>`, found `<{}>`",
+            root.tag
+        );
+    }
+
+    let mut title = String::new();
+    let mut preview = String::new();
+    let mut styles = String::new();
+    let mut breakpoint = BREAKPOINT.to_string();
+    let mut defaults = Defaults::default();
+    let mut body = None;
+
+    for child in root.children {
+        match child.tag.as_str() {
+            "mj-head" => {
+                for head in &child.children {
+                    match head.tag.as_str() {
+                        "mj-title" => title = head.content.trim().into(),
+                        "mj-preview" => preview = head.content.trim().into(),
+                        "mj-style" => styles.push_str(&head.content),
+                        "mj-attributes" => defaults.add(head)?,
+                        "mj-breakpoint" => {
+                            breakpoint = head.attribute("width", BREAKPOINT).into();
+                        }
+                        _ => return Err(head.unsupported()),
+                    }
+                }
+            }
+            "mj-body" => body = Some(child),
+            _ => return Err(child.unsupported()),
+        }
+    }
+    let Some(mut body) = body else {
+        bail!("The document is missing `<mj-body>`");
+    };
+    defaults.apply(&mut body);
+
+    let width = pixels(body.attribute("width", ""), BODY_WIDTH);
+    let background = body.attribute("background-color", "");
+
+    let mut content = Body::default();
+    for child in &body.children {
+        match child.tag.as_str() {
+            "mj-section" => section(&mut content, child, width)?,
+            "mj-wrapper" => wrapper(&mut content, child, width)?,
+            "mj-raw" => content.html.push_str(&child.content),
+            _ => return Err(child.unsupported()),
+        }
+    }
+
+    let mut html = format!(
+        "<!doctype html><html><head><title>{title}</title>\
+         <meta http-equiv=\"Content-Type\" content=\"text/html; charset=UTF-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">"
+    );
+    if !content.columns.is_empty() {
+        // Kept in the document when the CSS is inlined.
+        write!(
+            html,
+            "<style type=\"text/css\" data-css-inline=\"keep\">\
+             @media only screen and (min-width:{breakpoint}) {{ "
+        )?;
+        for (class, width) in &content.columns {
+            write!(
+                html,
+                ".{class} {{ width:{width} !important; max-width:{width}; }} "
+            )?;
+        }
+        html.push_str("}</style>");
+    }
+    if !styles.trim().is_empty() {
+        write!(html, "<style>{styles}</style>")?;
+    }
+    write!(
+        html,
+        "</head><body style=\"word-spacing:normal;background-color:{background};\">"
+    )?;
+    if !preview.is_empty() {
+        write!(
+            html,
+            "<div style=\"display:none;font-size:1px;line-height:1px;max-height:0px;\
+             max-width:0px;opacity:0;overflow:hidden;\">{preview}</div>"
+        )?;
+    }
+    write!(
+        html,
+        "<div style=\"background-color:{background};\">{}</div></body></html>",
+        content.html
+    )?;
+    Ok(html)
+}
+
+/// The start of the full width box of a section or wrapper.
+fn open_box(body: &mut Body, element: &Element, width: u32) -> Result<()> {
+    let background = element.attribute("background-color", "");
+    let padding = element.attribute("padding", "20px 0");
+    let align = element.attribute("text-align", "center");
+
+    write!(
+        body.html,
+        "<div style=\"margin:0px auto;max-width:{width}px;background:{background};\">\
+         <table align=\"center\" border=\"0\" cellpadding=\"0\" cellspacing=\"0\" \
+         role=\"presentation\" style=\"background:{background};width:100%;\"><tbody><tr>\
+         <td style=\"direction:ltr;font-size:0px;padding:{padding};text-align:{align};\">"
+    )?;
+    Ok(())
+}
+
+fn close_box(body: &mut Body) {
+    body.html.push_str("</td></tr></tbody></table></div>");
+}
+
+fn wrapper(body: &mut Body, wrapper: &Element, width: u32) -> Result<()> {
+    open_box(body, wrapper, width)?;
+    let inner = width.saturating_sub(horizontal_padding(wrapper.attribute("padding", "20px 0")));
+    body.html.push_str(
+        "<!--[if mso | IE]><table role=\"presentation\" border=\"0\" cellpadding=\"0\" \
+         cellspacing=\"0\"><![endif]-->",
+    );
+
+    for child in &wrapper.children {
+        match child.tag.as_str() {
+            "mj-section" => {
+                write!(
+                    body.html,
+                    "<!--[if mso | IE]><tr><td width=\"{inner}px\"><![endif]-->"
+                )?;
+                section(body, child, inner)?;
+                body.html
+                    .push_str("<!--[if mso | IE]></td></tr><![endif]-->");
+            }
+            "mj-raw" => body.html.push_str(&child.content),
+            _ => return Err(child.unsupported()),
+        }
+    }
+
+    body.html.push_str("<!--[if mso | IE]></table><![endif]-->");
+    close_box(body);
+    Ok(())
+}
+
+fn section(body: &mut Body, section: &Element, width: u32) -> Result<()> {
+    open_box(body, section, width)?;
+    body.html.push_str(
+        "<!--[if mso | IE]><table role=\"presentation\" border=\"0\" cellpadding=\"0\" \
+         cellspacing=\"0\"><tr><![endif]-->",
+    );
+
+    let count = section
+        .children
+        .iter()
+        .filter(|child| child.tag == "mj-column" || child.tag == "mj-group")
+        .count() as u32;
+    for child in &section.children {
+        match child.tag.as_str() {
+            "mj-column" => column(body, child, width, count, false)?,
+            "mj-group" => group(body, child, width, count)?,
+            "mj-raw" => body.html.push_str(&child.content),
+            _ => return Err(child.unsupported()),
+        }
+    }
+
+    body.html
+        .push_str("<!--[if mso | IE]></tr></table><![endif]-->");
+    close_box(body);
+    Ok(())
+}
+
+/// The width of a column or group that is one of `count` in a container `container` pixels
+/// wide. The width is added to the media queries of `body`.
+fn width(body: &mut Body, element: &Element, container: u32, count: u32) -> Result<Width> {
+    let width = match element.attribute("width", "") {
+        "" => percent_width(container, 100.0 / f64::from(count.max(1))),
+        width => match width.strip_suffix('%') {
+            Some(percent) => {
+                let percent = percent
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid width `{width}` on line {}", element.line))?;
+                percent_width(container, percent)
+            }
+            None => {
+                let pixels = pixels(width, container);
+                Width {
+                    pixels,
+                    css: format!("{pixels}px"),
+                    class: format!("mj-column-px-{pixels}"),
+                }
+            }
+        },
+    };
+
+    if !body.columns.iter().any(|(class, _)| *class == width.class) {
+        body.columns.push((width.class.clone(), width.css.clone()));
+    }
+    Ok(width)
+}
+
+fn percent_width(container: u32, percent: f64) -> Width {
+    Width {
+        pixels: (f64::from(container) * percent / 100.0) as u32,
+        css: format!("{percent}%"),
+        class: format!("mj-column-per-{}", percent.to_string().replace('.', "-")),
+    }
+}
+
+/// Columns that don't stack on small screens.
+fn group(body: &mut Body, group: &Element, container: u32, count: u32) -> Result<()> {
+    let width = width(body, group, container, count)?;
+    let background = group.attribute("background-color", "");
+    let align = group.attribute("vertical-align", "top");
+
+    write!(
+        body.html,
+        "<!--[if mso | IE]><td style=\"vertical-align:{align};width:{}px;\"><![endif]-->\
+         <div class=\"{} mj-outlook-group-fix\" style=\"font-size:0;line-height:0;\
+         text-align:left;display:inline-block;width:100%;direction:ltr;\
+         vertical-align:{align};background-color:{background};\">\
+         <!--[if mso | IE]><table border=\"0\" cellpadding=\"0\" cellspacing=\"0\" \
+         role=\"presentation\"><tr><![endif]-->",
+        width.pixels, width.class
+    )?;
+
+    let columns = group
+        .children
+        .iter()
+        .filter(|child| child.tag == "mj-column")
+        .count() as u32;
+    for child in &group.children {
+        match child.tag.as_str() {
+            "mj-column" => column(body, child, width.pixels, columns, true)?,
+            "mj-raw" => body.html.push_str(&child.content),
+            _ => return Err(child.unsupported()),
+        }
+    }
+
+    body.html.push_str(
+        "<!--[if mso | IE]></tr></table><![endif]--></div><!--[if mso | IE]></td><![endif]-->",
+    );
+    Ok(())
+}
+
+/// A column, taking the full width on small screens unless it's `in_group`.
+fn column(
+    body: &mut Body,
+    column: &Element,
+    container: u32,
+    count: u32,
+    in_group: bool,
+) -> Result<()> {
+    let width = width(body, column, container, count)?;
+    let small_width = if in_group { width.css.as_str() } else { "100%" };
+    let background = column.attribute("background-color", "");
+    let align = column.attribute("vertical-align", "top");
+
+    write!(
+        body.html,
+        "<!--[if mso | IE]><td style=\"vertical-align:{align};width:{}px;\"><![endif]-->\
+         <div class=\"{} mj-outlook-group-fix\" style=\"font-size:0px;text-align:left;\
+         direction:ltr;display:inline-block;vertical-align:{align};width:{small_width};\">\
+         <table border=\"0\" cellpadding=\"0\" cellspacing=\"0\" role=\"presentation\" \
+         style=\"background-color:{background};vertical-align:{align};\" width=\"100%\">\
+         <tbody>",
+        width.pixels, width.class
+    )?;
+
+    let html = &mut body.html;
+    for child in &column.children {
+        let default_align = match child.tag.as_str() {
+            "mj-button" | "mj-image" => "center",
+            _ => "left",
+        };
+        let align = child.attribute("align", default_align);
+        let padding = child.attribute("padding", "10px 25px");
+
+        if child.tag == "mj-raw" {
+            html.push_str(&child.content);
+            continue;
+        }
+        write!(
+            html,
+            "<tr><td align=\"{align}\" style=\"font-size:0px;padding:{padding};\
+             word-break:break-word;\">"
+        )?;
+        match child.tag.as_str() {
+            "mj-text" => text(html, child, align)?,
+            "mj-button" => button(html, child, align)?,
+            "mj-image" => image(html, child, width.pixels, padding)?,
+            "mj-divider" => divider(html, child)?,
+            "mj-spacer" => {
+                let height = child.attribute("height", "20px");
+                write!(
+                    html,
+                    "<div style=\"height:{height};line-height:{height};\">&#8202;</div>"
+                )?;
+            }
+            _ => return Err(child.unsupported()),
+        }
+        html.push_str("</td></tr>");
+    }
+
+    html.push_str("</tbody></table></div><!--[if mso | IE]></td><![endif]-->");
+    Ok(())
+}
+
+fn text(html: &mut String, text: &Element, align: &str) -> Result<()> {
+    write!(
+        html,
+        "<div style=\"font-family:{};font-size:{};line-height:{};text-align:{align};\
+         color:{};\">{}</div>",
+        text.attribute("font-family", FONT_FAMILY),
+        text.attribute("font-size", "13px"),
+        text.attribute("line-height", "1"),
+        text.attribute("color", "#000000"),
+        text.content
+    )?;
+    Ok(())
+}
+
+fn button(html: &mut String, button: &Element, align: &str) -> Result<()> {
+    let background = button.attribute("background-color", "#414141");
+    let color = button.attribute("color", "#ffffff");
+    let radius = button.attribute("border-radius", "3px");
+    let font_family = button.attribute("font-family", FONT_FAMILY);
+    let font_size = button.attribute("font-size", "13px");
+    let padding = button.attribute("inner-padding", "10px 25px");
+    let href = button.attribute("href", "#");
+
+    write!(
+        html,
+        "<table align=\"{align}\" border=\"0\" cellpadding=\"0\" cellspacing=\"0\" \
+         role=\"presentation\" style=\"border-collapse:separate;line-height:100%;\"><tbody><tr>\
+         <td align=\"center\" bgcolor=\"{background}\" role=\"presentation\" \
+         style=\"border:none;border-radius:{radius};cursor:auto;background:{background};\" \
+         valign=\"middle\"><a href=\"{href}\" style=\"display:inline-block;background:{background};\
+         color:{color};font-family:{font_family};font-size:{font_size};font-weight:normal;\
+         line-height:120%;margin:0;text-decoration:none;text-transform:none;padding:{padding};\
+         border-radius:{radius};\" target=\"_blank\">{}</a></td></tr></tbody></table>",
+        button.content.trim()
+    )?;
+    Ok(())
+}
+
+fn image(html: &mut String, image: &Element, column_width: u32, padding: &str) -> Result<()> {
+    let Some(src) = image.attributes.iter().find(|(name, _)| name == "src") else {
+        bail!("`<mj-image>` on line {} is missing `src`", image.line);
+    };
+    let available = column_width.saturating_sub(horizontal_padding(padding));
+    let width = pixels(image.attribute("width", ""), available).min(available);
+    let alt = image.attribute("alt", "");
+
+    let img = format!(
+        "<img alt=\"{alt}\" src=\"{}\" style=\"border:0;display:block;outline:none;\
+         text-decoration:none;height:auto;width:100%;font-size:13px;\" width=\"{width}\" \
+         height=\"auto\">",
+        src.1
+    );
+    let img = match image.attributes.iter().find(|(name, _)| name == "href") {
+        Some((_, href)) => format!("<a href=\"{href}\" target=\"_blank\">{img}</a>"),
+        None => img,
+    };
+    write!(
+        html,
+        "<table border=\"0\" cellpadding=\"0\" cellspacing=\"0\" role=\"presentation\" \
+         style=\"border-collapse:collapse;border-spacing:0px;\"><tbody><tr>\
+         <td style=\"width:{width}px;\">{img}</td></tr></tbody></table>"
+    )?;
+    Ok(())
+}
+
+fn divider(html: &mut String, divider: &Element) -> Result<()> {
+    write!(
+        html,
+        "<p style=\"border-top:{} {} {};font-size:1px;margin:0px auto;width:100%;\"></p>",
+        divider.attribute("border-style", "solid"),
+        divider.attribute("border-width", "4px"),
+        divider.attribute("border-color", "#000000"),
+    )?;
+    Ok(())
+}
+
+/// The pixels of a `px` value, `default` if it's missing or invalid.
+fn pixels(value: &str, default: u32) -> u32 {
+    value
+        .trim()
+        .trim_end_matches("px")
+        .parse()
+        .unwrap_or(default)
+}
+
+/// The sum of the left and right padding of a CSS `padding` value.
+fn horizontal_padding(padding: &str) -> u32 {
+    let values: Vec<u32> = padding.split_whitespace().map(|v| pixels(v, 0)).collect();
+    match values.as_slice() {
+        [all] => all * 2,
+        [_, horizontal] | [_, horizontal, _] => horizontal * 2,
+        [_, right, _, left] => right + left,
+        _ => 0,
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            position: 0,
+        }
+    }
+
+    fn document(&mut self) -> Result<Element> {
+        self.skip_whitespace_and_comments()?;
+        let root = self.element()?;
+        self.skip_whitespace_and_comments()?;
+        if self.position < self.source.len() {
+            bail!("Unexpected content after `</mjml>` on line {}", self.line());
+        }
+        Ok(root)
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.source[self.position..]
+    }
+
+    fn line(&self) -> usize {
+        self.source[..self.position].matches('\n').count() + 1
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<()> {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.position += rest.len() - trimmed.len();
+            if !trimmed.starts_with("<!--") {
+                return Ok(());
+            }
+            let Some(end) = trimmed.find("-->") else {
+                bail!("Unclosed comment on line {}", self.line());
+            };
+            self.position += end + 3;
+        }
+    }
+
+    fn element(&mut self) -> Result<Element> {
+        let line = self.line();
+        let Some(rest) = self.rest().strip_prefix('<') else {
+            bail!("Expected an element on line {line}");
+        };
+        let name_length = rest
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap_or(rest.len());
+        let tag = rest[..name_length].to_string();
+        if tag.is_empty() {
+            bail!("Expected an element on line {line}");
+        }
+        self.position += 1 + name_length;
+
+        let (attributes, self_closing) = self.attributes(&tag)?;
+        let mut element = Element {
+            tag,
+            attributes,
+            children: Vec::new(),
+            content: String::new(),
+            line,
+        };
+        if self_closing {
+            return Ok(element);
+        }
+
+        let close = format!("</{}>", element.tag);
+        if ENDING_TAGS.contains(&element.tag.as_str()) {
+            let Some(end) = self.rest().find(&close) else {
+                bail!("`<{}>` on line {line} is not closed", element.tag);
+            };
+            element.content = self.rest()[..end].to_string();
+            self.position += end + close.len();
+            return Ok(element);
+        }
+
+        loop {
+            self.skip_whitespace_and_comments()?;
+            if self.rest().starts_with(&close) {
+                self.position += close.len();
+                return Ok(element);
+            }
+            if self.rest().is_empty() {
+                bail!("`<{}>` on line {line} is not closed", element.tag);
+            }
+            if !self.rest().starts_with('<') || self.rest().starts_with("</") {
+                bail!(
+                    "Unexpected content in `<{}>` on line {}",
+                    element.tag,
+                    self.line()
+                );
+            }
+            element.children.push(self.element()?);
+        }
+    }
+
+    /// The attributes of the start tag and if it's self-closing.
+    fn attributes(&mut self, tag: &str) -> Result<(Vec<(String, String)>, bool)> {
+        let mut attributes = Vec::new();
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.position += rest.len() - trimmed.len();
+
+            if let Some(after) = trimmed.strip_prefix("/>") {
+                self.position = self.source.len() - after.len();
+                return Ok((attributes, true));
+            }
+            if let Some(after) = trimmed.strip_prefix('>') {
+                self.position = self.source.len() - after.len();
+                return Ok((attributes, false));
+            }
+
+            let Some(equals) = trimmed.find('=') else {
+                bail!("Invalid attribute in `<{tag}>` on line {}", self.line());
+            };
+            let name = trimmed[..equals].trim().to_string();
+            let value = trimmed[equals + 1..].trim_start();
+            let Some(quote) = value.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+                bail!("Unquoted attribute `{name}` on line {}", self.line());
+            };
+            let Some(end) = value[1..].find(quote) else {
+                bail!("Unclosed attribute `{name}` on line {}", self.line());
+            };
+            attributes.push((name, value[1..1 + end].to_string()));
+            self.position = self.source.len() - value[1 + end + 1..].len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compile;
+
+    #[test]
+    fn documents_are_compiled_to_tables() {
+        let html = compile(
+            r##"<mjml>
+              <mj-head>
+                <mj-title>Welcome</mj-title>
+                <mj-style>.highlight { color: red; }</mj-style>
+              </mj-head>
+              <mj-body background-color="#eeeeee">
+                <!-- Header -->
+                <mj-section>
+                  <mj-column width="50%">
+                    <mj-image src="cid:static~logo.png@assets" alt="Logo" />
+                  </mj-column>
+                  <mj-column>
+                    <mj-text font-size="20px">Hello <b>John</b></mj-text>
+                    <mj-button href="https://example.com/a?b=c&amp;d=e">Verify</mj-button>
+                  </mj-column>
+                </mj-section>
+              </mj-body>
+            </mjml>"##,
+        )
+        .unwrap();
+
+        assert!(html.starts_with("<!doctype html><html><head><title>Welcome</title>"));
+        assert!(html.contains("<style>.highlight { color: red; }</style>"));
+        assert!(html.contains("background-color:#eeeeee;"));
+        assert!(html.contains(
+            "@media only screen and (min-width:480px) { \
+             .mj-column-per-50 { width:50% !important; max-width:50%; } }"
+        ));
+        assert!(html.contains(r#"<div class="mj-column-per-50 mj-outlook-group-fix""#));
+        assert!(html.contains("vertical-align:top;width:100%;"));
+        assert!(html.contains(r#"src="cid:static~logo.png@assets""#));
+        assert!(html.contains(r#"width="250""#));
+        assert!(html.contains(
+            r#"font-size:20px;line-height:1;text-align:left;color:#000000;">Hello <b>John</b></div>"#
+        ));
+        assert!(html.contains(r#"<a href="https://example.com/a?b=c&amp;d=e""#));
+        assert!(!html.contains("<mj-"));
+    }
+
+    #[test]
+    fn attributes_default_to_the_ones_of_classes_tags_and_all() {
+        let html = compile(
+            r##"<mjml>
+              <mj-head>
+                <mj-attributes>
+                  <mj-all font-family="Arial" />
+                  <mj-text color="#111111" font-size="14px" />
+                  <mj-class name="large" font-size="24px" />
+                </mj-attributes>
+              </mj-head>
+              <mj-body>
+                <mj-section>
+                  <mj-column>
+                    <mj-text>Plain</mj-text>
+                    <mj-text mj-class="large">Large</mj-text>
+                    <mj-text mj-class="large" font-size="32px" color="red">Own</mj-text>
+                  </mj-column>
+                </mj-section>
+              </mj-body>
+            </mjml>"##,
+        )
+        .unwrap();
+
+        assert!(html.contains(
+            "font-family:Arial;font-size:14px;line-height:1;text-align:left;color:#111111;\">Plain"
+        ));
+        assert!(
+            html.contains("font-size:24px;line-height:1;text-align:left;color:#111111;\">Large")
+        );
+        assert!(html.contains("font-size:32px;line-height:1;text-align:left;color:red;\">Own"));
+    }
+
+    #[test]
+    fn wrappers_and_groups_are_laid_out_with_media_queries() {
+        let html = compile(
+            r##"<mjml>
+              <mj-head><mj-breakpoint width="320px" /></mj-head>
+              <mj-body>
+                <mj-wrapper padding="20px 50px" background-color="#ffffff">
+                  <mj-section>
+                    <mj-column width="200px"><mj-text>Side</mj-text></mj-column>
+                    <mj-group>
+                      <mj-column><mj-text>Left</mj-text></mj-column>
+                      <mj-column><mj-text>Right</mj-text></mj-column>
+                    </mj-group>
+                  </mj-section>
+                </mj-wrapper>
+              </mj-body>
+            </mjml>"##,
+        )
+        .unwrap();
+
+        assert!(html.contains(
+            "@media only screen and (min-width:320px) { \
+             .mj-column-px-200 { width:200px !important; max-width:200px; } \
+             .mj-column-per-50 { width:50% !important; max-width:50%; } }"
+        ));
+        // The sections of the wrapper are as wide as its content.
+        assert!(html.contains(r#"<tr><td width="500px">"#));
+        assert!(html.contains("max-width:500px;"));
+        // The group's columns keep their width on small screens.
+        assert!(html.contains(r#"<div class="mj-column-per-50 mj-outlook-group-fix""#));
+        assert!(html.contains("vertical-align:top;width:50%;\">"));
+        assert!(!html.contains("<mj-"));
+    }
+
+    #[test]
+    fn errors_point_to_the_line() {
+        let error = |mjml: &str| compile(mjml).unwrap_err().to_string();
+
+        assert_eq!(
+            error("<mjml>\n<mj-body>\n<mj-hero></mj-hero>\n</mj-body>\n</mjml>"),
+            "Unsupported MJML element `<mj-hero>` on line 3"
+        );
+        assert_eq!(
+            error("<mjml>\n<mj-body>\n<mj-section>\n</mj-body></mjml>"),
+            "Unexpected content in `<mj-section>` on line 4"
+        );
+        assert_eq!(
+            error("<mjml><mj-body><mj-section><mj-column>\n<mj-text>Hello</mj-column>"),
+            "`<mj-text>` on line 2 is not closed"
+        );
+    }
+}
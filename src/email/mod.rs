@@ -1,4 +1,28 @@
+mod address;
+pub mod attachment;
 mod config;
+pub mod css;
+pub mod dkim;
+pub mod events;
+mod file;
+pub mod intercept;
+mod mailer;
+pub mod mailing_list;
+mod memory;
+pub mod mjml;
+pub mod outbox;
+pub mod preview;
+pub mod rate_limit;
+mod smtp;
+pub mod suppression;
+mod template;
 pub mod verification;
 
+pub use address::Address;
+pub use attachment::Attachment;
 pub use config::*;
+pub use file::FileMailer;
+pub use mailer::{Config as TransportConfig, Email, Mailer, Transport};
+pub use memory::MemoryMailer;
+pub use smtp::SmtpMailer;
+pub use template::{locales, render, Content};
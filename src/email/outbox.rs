@@ -0,0 +1,551 @@
+//! The transactional email outbox.
+//!
+//! Emails are written to the `email_outbox` table, in the same transaction as the change they
+//! are about, and delivered by a background worker. Failed deliveries are retried with an
+//! exponential backoff. After `max_attempts` failures, the email is marked as dead and can only
+//! be retried by an admin. Emails to suppressed addresses are not sent, see
+//! [`super::suppression`], neither are mailing list emails to users who unsubscribed, see
+//! [`super::mailing_list`].
+
+use std::{path::Path, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use chrono::{DateTime, FixedOffset};
+use entity::{
+    email_outbox::{self, Status},
+    mailing_list_subscription,
+};
+use sea_orm::{
+    prelude::*,
+    sea_query::{LockBehavior, LockType},
+    ActiveValue::Set,
+    ConnectionTrait, DatabaseConnection, QueryOrder, QuerySelect, TransactionTrait,
+};
+use serde::Deserialize;
+use serde_json::json;
+use tower_sessions::Session;
+
+use super::{mailing_list, suppression, Attachment, Email, Mailer, TransportConfig};
+use crate::{user::authenticated::NotImpersonated, AppError, StackZero};
+
+/// The maximum number of emails delivered in one run of the worker.
+const DELIVERY_BATCH: usize = 100;
+
+/// How long an email is claimed by the worker delivering it.
+const DELIVERY_CLAIM: Duration = Duration::from_secs(10 * 60);
+
+/// The maximum number of emails listed in the admin view.
+const LIST_LIMIT: u64 = 200;
+
+impl TransportConfig {
+    /// The delay before the next attempt after `attempts` failed attempts.
+    pub fn retry_delay(&self, attempts: u32) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(63);
+        let delay = self.retry_backoff.saturating_mul(1u64 << exponent);
+        Duration::from_secs(delay.min(self.max_retry_backoff))
+    }
+}
+
+/// Write the email to the outbox.
+pub async fn enqueue(
+    connection: &impl ConnectionTrait,
+    email: &Email,
+    date: DateTime<FixedOffset>,
+) -> Result<email_outbox::Model> {
+    insert(connection, email, None, date).await
+}
+
+/// Write a mailing list email to the outbox. It is only delivered if the user is still
+/// subscribed.
+pub async fn enqueue_to_subscriber(
+    connection: &impl ConnectionTrait,
+    email: &Email,
+    subscription: &mailing_list_subscription::Model,
+    date: DateTime<FixedOffset>,
+) -> Result<email_outbox::Model> {
+    insert(connection, email, Some(subscription), date).await
+}
+
+async fn insert(
+    connection: &impl ConnectionTrait,
+    email: &Email,
+    subscription: Option<&mailing_list_subscription::Model>,
+    date: DateTime<FixedOffset>,
+) -> Result<email_outbox::Model> {
+    let entry = email_outbox::Model {
+        id: Uuid::new_v4(),
+        from: email.from.to_string(),
+        to: email.to.to_string(),
+        subject: email.subject.clone(),
+        html: email.html.clone(),
+        text: email.text.clone(),
+        attachments: serde_json::to_value(
+            email
+                .attachments
+                .iter()
+                .map(|(attachment, _)| attachment)
+                .collect::<Vec<_>>(),
+        )?,
+        headers: serde_json::to_value(&email.headers)?,
+        mailing_list_id: subscription.map(|subscription| subscription.mailing_list_id),
+        user_id: subscription.map(|subscription| subscription.user_id),
+        status: Status::Pending,
+        attempts: 0,
+        next_attempt_date: date,
+        last_error: None,
+        creation_date: date,
+        sent_date: None,
+    };
+
+    email_outbox::Entity::insert(email_outbox::ActiveModel::from(entry.clone()))
+        .exec(connection)
+        .await?;
+
+    Ok(entry)
+}
+
+/// Deliver the emails that are due. Returns the number of delivered emails.
+///
+/// Emails are claimed before they are delivered, so that several workers can run concurrently.
+pub async fn deliver_due(
+    connection: &DatabaseConnection,
+    mailer: &dyn Mailer,
+    config: &TransportConfig,
+    assets: &Path,
+    date: DateTime<FixedOffset>,
+) -> Result<usize> {
+    let mut delivered = 0;
+    for _ in 0..DELIVERY_BATCH {
+        match deliver_next(connection, mailer, config, assets, date).await? {
+            Some(true) => delivered += 1,
+            Some(false) => {}
+            None => break,
+        }
+    }
+    Ok(delivered)
+}
+
+/// Deliver the next due email. Returns `None` if there is none, otherwise if the delivery
+/// succeeded.
+///
+/// The email is claimed by postponing its next attempt by [`DELIVERY_CLAIM`] in a short
+/// transaction, no lock is held while it is sent. If the worker dies while sending, the email is
+/// attempted again after the claim elapsed.
+async fn deliver_next(
+    connection: &DatabaseConnection,
+    mailer: &dyn Mailer,
+    config: &TransportConfig,
+    assets: &Path,
+    date: DateTime<FixedOffset>,
+) -> Result<Option<bool>> {
+    let txn = connection.begin().await?;
+
+    let Some(entry) = email_outbox::Entity::find()
+        .filter(email_outbox::Column::Status.eq(Status::Pending))
+        .filter(email_outbox::Column::NextAttemptDate.lte(date))
+        .order_by_asc(email_outbox::Column::NextAttemptDate)
+        .lock_with_behavior(LockType::Update, LockBehavior::SkipLocked)
+        .one(&txn)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let email = email(&entry, assets);
+    if let Ok(email) = &email {
+        let to = email.to.email.to_string();
+        let skipped = if let Some(suppression) = suppression::find(&txn, &to).await? {
+            Some((
+                Status::Suppressed,
+                format!("The recipient is suppressed: {:?}", suppression.reason),
+            ))
+        } else if let (Some(mailing_list), Some(user)) = (entry.mailing_list_id, entry.user_id) {
+            (!mailing_list::is_subscribed(&txn, mailing_list, user).await?).then(|| {
+                (
+                    Status::Unsubscribed,
+                    "The recipient unsubscribed from the mailing list".into(),
+                )
+            })
+        } else {
+            None
+        };
+
+        if let Some((status, reason)) = skipped {
+            let mut entry = email_outbox::ActiveModel::from(entry);
+            entry.status = Set(status);
+            entry.last_error = Set(Some(reason));
+            entry.update(&txn).await?;
+            txn.commit().await?;
+            return Ok(Some(false));
+        }
+    }
+
+    let mut entry = email_outbox::ActiveModel::from(entry);
+    entry.next_attempt_date = Set(date + chrono::Duration::from_std(DELIVERY_CLAIM)?);
+    let entry = entry.update(&txn).await?;
+    txn.commit().await?;
+
+    let result = match email {
+        Ok(email) => mailer.send(&email).await,
+        Err(e) => Err(e),
+    };
+
+    let id = entry.id;
+    let attempts = entry.attempts.saturating_add(1);
+    let mut entry = email_outbox::ActiveModel::from(entry);
+    match &result {
+        Ok(()) => {
+            entry.status = Set(Status::Sent);
+            entry.sent_date = Set(Some(date));
+            entry.last_error = Set(None);
+        }
+        Err(e) => {
+            eprintln!("Delivering email {id} failed (attempt {attempts}): {e:?}");
+            entry.attempts = Set(attempts);
+            entry.last_error = Set(Some(format!("{e:#}")));
+            if attempts as u32 >= config.max_attempts {
+                entry.status = Set(Status::Dead);
+            } else {
+                let delay = chrono::Duration::from_std(config.retry_delay(attempts as u32))?;
+                entry.next_attempt_date = Set(date + delay);
+            }
+        }
+    }
+    entry.update(connection).await?;
+
+    Ok(Some(result.is_ok()))
+}
+
+/// The email of the entry, with the attachments read from the `assets` directory.
+fn email(entry: &email_outbox::Model, assets: &Path) -> Result<Email> {
+    let attachments: Vec<Attachment> =
+        serde_json::from_value(entry.attachments.clone()).context("Invalid attachments")?;
+    let headers = serde_json::from_value(entry.headers.clone()).context("Invalid headers")?;
+    Ok(Email {
+        from: entry.from.parse().context("Invalid from address")?,
+        to: entry.to.parse().context("Invalid recipient address")?,
+        subject: entry.subject.clone(),
+        html: entry.html.clone(),
+        text: entry.text.clone(),
+        attachments: attachments
+            .into_iter()
+            .map(|attachment| {
+                let body = attachment.load(assets)?;
+                Ok((attachment, body))
+            })
+            .collect::<Result<_>>()?,
+        headers,
+    })
+}
+
+/// The most recent emails, optionally only the ones with `status`.
+pub async fn list(
+    connection: &DatabaseConnection,
+    status: Option<Status>,
+) -> Result<Vec<email_outbox::Model>> {
+    let mut query = email_outbox::Entity::find();
+    if let Some(status) = status {
+        query = query.filter(email_outbox::Column::Status.eq(status));
+    }
+    Ok(query
+        .order_by_desc(email_outbox::Column::CreationDate)
+        .limit(LIST_LIMIT)
+        .all(connection)
+        .await?)
+}
+
+/// Deliver a dead or pending email again, starting over with its attempts.
+pub async fn retry(
+    connection: &DatabaseConnection,
+    id: Uuid,
+    date: DateTime<FixedOffset>,
+) -> Result<Option<email_outbox::Model>> {
+    let Some(entry) = email_outbox::Entity::find_by_id(id).one(connection).await? else {
+        return Ok(None);
+    };
+    if entry.status == Status::Sent {
+        return Ok(Some(entry));
+    }
+
+    let mut entry = email_outbox::ActiveModel::from(entry);
+    entry.status = Set(Status::Pending);
+    entry.attempts = Set(0);
+    entry.next_attempt_date = Set(date);
+    Ok(Some(entry.update(connection).await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    status: Option<Status>,
+}
+
+/// The admin page listing the emails of the outbox.
+pub async fn page(
+    State(state): State<Arc<StackZero>>,
+    NotImpersonated(user): NotImpersonated,
+    session: Session,
+    Query(query): Query<ListQuery>,
+) -> Result<Response, AppError> {
+    if !user.admin {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+
+    let emails = list(&state.db_connection, query.status).await?;
+    let page = state
+        .render_for_session(
+            &session,
+            "admin/emails",
+            json! {{"status": query.status, "emails": emails}},
+        )
+        .await?;
+
+    Ok(Html(page).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use anyhow::{anyhow, Result};
+    use axum::async_trait;
+    use chrono::{DateTime, FixedOffset, SubsecRound, Utc};
+    use entity::{
+        email_outbox::{self, Status},
+        email_suppression::Reason,
+    };
+    use sea_orm::{DatabaseConnection, EntityTrait};
+
+    use super::{deliver_due, enqueue, enqueue_to_subscriber, retry};
+    use crate::{
+        email::{mailing_list, suppression, Content, Email, Mailer, MemoryMailer, TransportConfig},
+        test_helper,
+        user::users::{self, AuthenticationMethod},
+    };
+
+    #[derive(Debug)]
+    struct FailingMailer;
+
+    #[async_trait]
+    impl Mailer for FailingMailer {
+        async fn send(&self, _email: &Email) -> Result<()> {
+            Err(anyhow!("Connection refused"))
+        }
+    }
+
+    /// Dates are stored with microseconds.
+    fn now() -> DateTime<FixedOffset> {
+        Utc::now().trunc_subsecs(0).into()
+    }
+
+    fn email(to: &str) -> Email {
+        Email::new(
+            "noreply@example.com".parse().unwrap(),
+            to,
+            Content::from_html("Hello", "<p>Hello</p>".into()),
+            Path::new("assets"),
+        )
+        .unwrap()
+    }
+
+    async fn get(
+        connection: &DatabaseConnection,
+        entry: &email_outbox::Model,
+    ) -> email_outbox::Model {
+        email_outbox::Entity::find_by_id(entry.id)
+            .one(connection)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Docker"]
+    async fn pending_emails_are_sent() {
+        let connection = test_helper::database().await.unwrap();
+        let mailer = MemoryMailer::default();
+        let config = TransportConfig::default();
+        let date = now();
+
+        let entry = enqueue(&connection, &email("john@doe.com"), date)
+            .await
+            .unwrap();
+        let delivered = deliver_due(&connection, &mailer, &config, Path::new("assets"), date)
+            .await
+            .unwrap();
+
+        assert_eq!(delivered, 1);
+        let entry = get(&connection, &entry).await;
+        assert_eq!(entry.status, Status::Sent);
+        assert_eq!(entry.sent_date, Some(date));
+        assert_eq!(mailer.expect_sent_to("john@doe.com").subject, "Hello");
+
+        // Sent emails are not delivered again.
+        let delivered = deliver_due(&connection, &mailer, &config, Path::new("assets"), date)
+            .await
+            .unwrap();
+        assert_eq!(delivered, 0);
+        assert_eq!(mailer.sent().len(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Docker"]
+    async fn failed_deliveries_back_off_until_they_are_dead_and_can_be_retried() {
+        let connection = test_helper::database().await.unwrap();
+        let config = TransportConfig {
+            max_attempts: 2,
+            retry_backoff: 60,
+            ..Default::default()
+        };
+        let assets = Path::new("assets");
+        let date = now();
+
+        let entry = enqueue(&connection, &email("john@doe.com"), date)
+            .await
+            .unwrap();
+
+        deliver_due(&connection, &FailingMailer, &config, assets, date)
+            .await
+            .unwrap();
+        let failed = get(&connection, &entry).await;
+        assert_eq!(failed.status, Status::Pending);
+        assert_eq!(failed.attempts, 1);
+        assert_eq!(
+            failed.next_attempt_date,
+            date + chrono::Duration::seconds(60)
+        );
+        assert!(failed.last_error.unwrap().contains("Connection refused"));
+
+        // Not due before the backoff elapsed.
+        let mailer = MemoryMailer::default();
+        deliver_due(&connection, &mailer, &config, assets, date)
+            .await
+            .unwrap();
+        assert!(mailer.sent().is_empty());
+
+        let later = failed.next_attempt_date;
+        deliver_due(&connection, &FailingMailer, &config, assets, later)
+            .await
+            .unwrap();
+        let dead = get(&connection, &entry).await;
+        assert_eq!(dead.status, Status::Dead);
+        assert_eq!(dead.attempts, 2);
+
+        // Dead emails are only delivered after a retry.
+        let much_later = later + chrono::Duration::days(1);
+        deliver_due(&connection, &mailer, &config, assets, much_later)
+            .await
+            .unwrap();
+        assert!(mailer.sent().is_empty());
+
+        let retried = retry(&connection, entry.id, much_later)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(retried.status, Status::Pending);
+        assert_eq!(retried.attempts, 0);
+        deliver_due(&connection, &mailer, &config, assets, much_later)
+            .await
+            .unwrap();
+        assert_eq!(get(&connection, &entry).await.status, Status::Sent);
+        mailer.expect_sent_to("john@doe.com");
+
+        // Sent emails are not retried.
+        let sent = retry(&connection, entry.id, much_later)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sent.status, Status::Sent);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Docker"]
+    async fn emails_to_suppressed_addresses_are_not_sent() {
+        let connection = test_helper::database().await.unwrap();
+        let mailer = MemoryMailer::default();
+        let date = now();
+
+        suppression::suppress(&connection, "John@Doe.com", Reason::Bounce, None, date)
+            .await
+            .unwrap();
+        let entry = enqueue(&connection, &email("john@doe.com"), date)
+            .await
+            .unwrap();
+        let delivered = deliver_due(
+            &connection,
+            &mailer,
+            &TransportConfig::default(),
+            Path::new("assets"),
+            date,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(delivered, 0);
+        assert!(mailer.sent().is_empty());
+        let entry = get(&connection, &entry).await;
+        assert_eq!(entry.status, Status::Suppressed);
+        assert!(entry.last_error.unwrap().contains("Bounce"));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Docker"]
+    async fn list_emails_to_unsubscribed_users_are_not_sent() {
+        let connection = test_helper::database().await.unwrap();
+        let mailer = MemoryMailer::default();
+        let date = now();
+
+        let user = users::create(
+            &connection,
+            "John",
+            &"john@doe.com".parse().unwrap(),
+            AuthenticationMethod::SingleSignOn,
+            None,
+            date,
+        )
+        .await
+        .unwrap();
+        let list = mailing_list::create(&connection, "news", "News", "", date)
+            .await
+            .unwrap();
+        mailing_list::subscribe(&connection, list.id, user.id, date)
+            .await
+            .unwrap();
+        let subscription = mailing_list::subscription(&connection, list.id, user.id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let entry = enqueue_to_subscriber(&connection, &email(&user.email), &subscription, date)
+            .await
+            .unwrap();
+        mailing_list::unsubscribe(&connection, list.id, user.id, date)
+            .await
+            .unwrap();
+        let delivered = deliver_due(
+            &connection,
+            &mailer,
+            &TransportConfig::default(),
+            Path::new("assets"),
+            date,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(delivered, 0);
+        assert!(mailer.sent().is_empty());
+        assert_eq!(get(&connection, &entry).await.status, Status::Unsubscribed);
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_maximum() {
+        let config = TransportConfig::default();
+        assert_eq!(config.retry_delay(1), Duration::from_secs(60));
+        assert_eq!(config.retry_delay(3), Duration::from_secs(240));
+        assert_eq!(config.retry_delay(100), Duration::from_secs(6 * 60 * 60));
+    }
+}
@@ -0,0 +1,136 @@
+//! Previews of the email templates, for development only.
+//!
+//! Each email template `key` is rendered with the fixture data in `{key}.json`, next to the
+//! template. `site` is set to the base URL unless the fixture defines it.
+
+use std::{fs, sync::Arc};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use tower_sessions::Session;
+
+use super::{Content, Email};
+use crate::{respond, user::authenticated::Authenticated, AppError, StackZero};
+
+/// The prefix of the routes.
+pub const PATH: &str = "/dev/emails";
+
+#[derive(Debug, Serialize)]
+struct Preview {
+    key: String,
+    subject: String,
+    html: String,
+    text: String,
+    headers: String,
+}
+
+/// The keys of all email templates.
+fn keys(state: &StackZero) -> Vec<String> {
+    let mut keys: Vec<String> = state
+        .template_renderer
+        .template_names()
+        .filter(|name| name.starts_with("emails/"))
+        .filter_map(|name| name.strip_suffix(".subject.txt"))
+        .map(Into::into)
+        .collect();
+    keys.sort();
+    keys
+}
+
+/// The fixture data of the template `key`.
+fn fixture(state: &StackZero, key: &str) -> Result<Value> {
+    let path = state.config.template_dir.join(format!("{key}.json"));
+    let mut data = match fs::read_to_string(&path) {
+        Ok(json) => serde_json::from_str(&json)
+            .with_context(|| format!("Invalid fixture data in {path:?}"))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Map::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {path:?}")),
+    };
+    data.entry("site")
+        .or_insert_with(|| state.config.base_url.as_str().into());
+    Ok(Value::Object(data))
+}
+
+/// Render the email template `name`, if it exists.
+fn render(state: &StackZero, name: &str) -> Result<Option<Content>> {
+    let key = format!("emails/{name}");
+    // Only existing templates, the name is also used for the path of the fixture.
+    if !keys(state).contains(&key) {
+        return Ok(None);
+    }
+    Ok(Some(state.render_email(
+        &key,
+        None,
+        fixture(state, &key)?,
+    )?))
+}
+
+/// Lists the email templates.
+pub async fn index(
+    State(state): State<Arc<StackZero>>,
+    session: Session,
+) -> Result<Response, AppError> {
+    let page = state
+        .render_for_session(
+            &session,
+            "dev/emails",
+            json! {{"path": PATH, "templates": keys(&state)}},
+        )
+        .await?;
+    respond::html(&page)
+}
+
+/// Shows the inlined HTML, the text part, and the headers of an email template.
+pub async fn show(
+    State(state): State<Arc<StackZero>>,
+    session: Session,
+    Path(name): Path<String>,
+) -> Result<Response, AppError> {
+    let Some(content) = render(&state, &name)? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    // Rendered for a placeholder recipient, the headers show what the transport receives.
+    let email = Email::new(
+        state.email_from.clone(),
+        "recipient@example.com",
+        content,
+        &state.config.template_dir,
+    )?;
+    let headers = email.message()?.headers().to_string();
+
+    let preview = Preview {
+        key: format!("emails/{name}"),
+        subject: email.subject,
+        html: email.html,
+        text: email.text,
+        headers,
+    };
+    let page = state
+        .render_for_session(
+            &session,
+            "dev/email",
+            json! {{"path": PATH, "name": name, "preview": preview}},
+        )
+        .await?;
+    respond::html(&page)
+}
+
+/// Sends an email template to the current user with the configured transport.
+pub async fn send(
+    State(state): State<Arc<StackZero>>,
+    Authenticated(user): Authenticated,
+    Path(name): Path<String>,
+) -> Result<Response, AppError> {
+    let Some(content) = render(&state, &name)? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    state.send_email(&user.email, content).await?;
+    respond::redirect(&format!("{PATH}/{name}"))
+}
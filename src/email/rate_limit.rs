@@ -0,0 +1,108 @@
+//! Rate limits for requests that send emails.
+//!
+//! Emails triggered by requests are counted per recipient and per IP address of the client in
+//! a fixed window. The limits are checked before anything else is looked up, so limited
+//! requests don't reveal if an address belongs to an account.
+
+use std::{net::IpAddr, time::Duration};
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use super::Address;
+use crate::throttle::Throttle;
+
+/// The `[email.rate_limit]` section.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Emails per recipient address in a window.
+    pub max_per_address: u64,
+    /// Emails triggered from an IP address in a window.
+    pub max_per_ip: u64,
+    /// Seconds the emails are counted.
+    pub window: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_per_address: 5,
+            max_per_ip: 20,
+            window: 60 * 60,
+        }
+    }
+}
+
+/// Count an email to `to` triggered from `ip`. Returns the time until emails are allowed again
+/// if a limit is exceeded.
+pub async fn retry_after(
+    throttle: &Throttle,
+    config: &Config,
+    to: &Address,
+    ip: Option<IpAddr>,
+) -> Result<Option<Duration>> {
+    let window = Duration::from_secs(config.window);
+
+    let mut limits = vec![(address_key(to), config.max_per_address)];
+    if let Some(ip) = ip {
+        limits.push((ip_key(ip), config.max_per_ip));
+    }
+
+    let mut retry_after = None;
+    for (key, max) in limits {
+        if throttle.increment(&key, window).await? > max {
+            let ttl = throttle.time_to_live(&key).await?.unwrap_or(window);
+            retry_after = retry_after.max(Some(ttl));
+        }
+    }
+    Ok(retry_after)
+}
+
+fn address_key(address: &Address) -> String {
+    format!("email:address:{}", address.as_str().to_lowercase())
+}
+
+fn ip_key(ip: IpAddr) -> String {
+    format!("email:ip:{ip}")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::{retry_after, Config};
+    use crate::{email::Address, throttle::Throttle};
+
+    #[tokio::test]
+    async fn emails_are_limited_per_address_and_ip() {
+        let throttle = Throttle::memory();
+        let config = Config {
+            max_per_address: 2,
+            max_per_ip: 2,
+            window: 60,
+        };
+        let john: Address = "John@doe.com".parse().unwrap();
+        let jane: Address = "jane@doe.com".parse().unwrap();
+        let bob: Address = "bob@doe.com".parse().unwrap();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let limited = |to: &Address, ip| {
+            let (throttle, config, to) = (&throttle, &config, to.clone());
+            async move {
+                retry_after(throttle, config, &to, ip)
+                    .await
+                    .unwrap()
+                    .is_some()
+            }
+        };
+
+        assert!(!limited(&john, Some(ip)).await);
+        assert!(!limited(&"john@doe.com".parse().unwrap(), None).await);
+        assert!(limited(&john, None).await);
+
+        assert!(!limited(&jane, Some(ip)).await);
+        // The third email from the IP address.
+        assert!(limited(&jane, Some(ip)).await);
+        assert!(!limited(&bob, None).await);
+    }
+}
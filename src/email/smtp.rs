@@ -0,0 +1,117 @@
+//! Delivering emails over SMTP.
+
+use anyhow::{Context, Result};
+use axum::async_trait;
+use std::sync::Arc;
+
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+
+use super::{dkim::Dkim, EffectiveSmtp, Email, Mailer};
+
+#[derive(Debug, Clone)]
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    dkim: Option<Arc<Dkim>>,
+}
+
+impl SmtpMailer {
+    pub fn new(smtp: &EffectiveSmtp, dkim: Option<Arc<Dkim>>) -> Result<Self> {
+        Ok(Self {
+            transport: smtp.transport()?,
+            dkim,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: &Email) -> Result<()> {
+        self.transport
+            .send(email.signed_message(self.dkim.as_deref())?)
+            .await
+            .with_context(|| format!("Sending email to {}", email.to))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::Future, path::Path};
+
+    use anyhow::Result;
+    use rstest::rstest;
+    use url::Url;
+
+    use super::SmtpMailer;
+    use crate::{
+        email::{self, Content, Email, Mailer},
+        test_helper::*,
+    };
+
+    fn config(port: u16, username: &str) -> email::Config {
+        toml::from_str(&format!(
+            r#"
+            server = "127.0.0.1:{port}"
+            [credentials]
+            username = "{username}"
+            password = "secret"
+            security = "none"
+            "#
+        ))
+        .unwrap()
+    }
+
+    fn mailer(config: email::Config) -> SmtpMailer {
+        let site: Url = "https://example.com".parse().unwrap();
+        SmtpMailer::new(&config.into_effective(&site).unwrap(), None).unwrap()
+    }
+
+    fn email(html: &str) -> Email {
+        let from = "noreply@example.com".parse().unwrap();
+        Email::new(
+            from,
+            "john@doe.com",
+            Content::from_html("Hello", html.into()),
+            Path::new("assets"),
+        )
+        .unwrap()
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn sends_to_the_server(
+        smtp_server: impl Future<Output = Result<SmtpServer>>,
+    ) -> Result<()> {
+        let mut server = smtp_server.await?;
+        let mailer = mailer(config(server.port, ""));
+
+        mailer.send(&email("<p>Hello John</p>")).await?;
+
+        let mail = server.received.recv().await.unwrap();
+        assert_eq!(mail.auth, None);
+        assert_eq!(mail.from, "noreply@example.com");
+        assert_eq!(mail.to, vec!["john@doe.com".to_string()]);
+        assert!(mail.data.contains("Subject: Hello\r\n"));
+        assert!(mail
+            .data
+            .contains("Content-Type: text/html; charset=utf-8\r\n"));
+        assert!(mail.data.contains("<p>Hello John</p>"));
+        Ok(())
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn authenticates_with_the_credentials(
+        smtp_server: impl Future<Output = Result<SmtpServer>>,
+    ) -> Result<()> {
+        let mut server = smtp_server.await?;
+        let mailer = mailer(config(server.port, "user"));
+
+        mailer.send(&email("Hello")).await?;
+
+        let mail = server.received.recv().await.unwrap();
+        // base64 of "\0user\0secret"
+        assert_eq!(mail.auth.as_deref(), Some("AHVzZXIAc2VjcmV0"));
+        Ok(())
+    }
+}
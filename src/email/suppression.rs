@@ -0,0 +1,56 @@
+//! Addresses emails are not sent to.
+//!
+//! Addresses are suppressed after they hard-bounced or the recipient complained, as reported
+//! by the email service, see [`super::events`]. The outbox skips emails to suppressed
+//! addresses.
+
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset};
+use entity::email_suppression::{self, Reason};
+use sea_orm::{prelude::*, sea_query::OnConflict, ConnectionTrait};
+
+/// The suppression of `email`, if it is suppressed.
+pub async fn find(
+    connection: &impl ConnectionTrait,
+    email: &str,
+) -> Result<Option<email_suppression::Model>> {
+    Ok(email_suppression::Entity::find_by_id(email.to_lowercase())
+        .one(connection)
+        .await?)
+}
+
+/// Suppress `email`. An existing suppression of the address is kept.
+pub async fn suppress(
+    connection: &impl ConnectionTrait,
+    email: &str,
+    reason: Reason,
+    details: Option<String>,
+    date: DateTime<FixedOffset>,
+) -> Result<()> {
+    let suppression = email_suppression::Model {
+        email: email.to_lowercase(),
+        reason,
+        details,
+        creation_date: date,
+    };
+
+    email_suppression::Entity::insert(email_suppression::ActiveModel::from(suppression))
+        .on_conflict(
+            OnConflict::column(email_suppression::Column::Email)
+                .do_nothing()
+                .to_owned(),
+        )
+        .do_nothing()
+        .exec(connection)
+        .await?;
+
+    Ok(())
+}
+
+/// Send emails to `email` again. Returns `false` if it was not suppressed.
+pub async fn remove(connection: &impl ConnectionTrait, email: &str) -> Result<bool> {
+    let result = email_suppression::Entity::delete_by_id(email.to_lowercase())
+        .exec(connection)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
@@ -0,0 +1,282 @@
+//! Emails rendered from templates.
+//!
+//! An email template `key` consists of the sibling templates:
+//!
+//! - `{key}.subject.txt`, the subject line.
+//! - `{key}.html`, the HTML body, or `{key}.mjml.html` to write it in MJML. Only a subset of
+//!   MJML is supported, see [`super::mjml`]. MJML templates are rendered by Tera, compiled to
+//!   HTML and their CSS is inlined.
+//! - `{key}.txt`, the plain-text body. Optional, if missing, it is generated from the HTML body.
+//!
+//! Localized templates are placed in a directory named after the locale, below the first
+//! directory of the key: `emails/de/email_verification` localizes `emails/email_verification`.
+//! The subject and the HTML body fall back separately from the locale to its language and then
+//! to the unlocalized template, so a locale may only translate the subject. The plain-text body
+//! is taken from the same locale as the HTML body.
+//!
+//! Images embedded with the `cid` template function are attached to the content, see
+//! [`super::attachment`].
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use super::{attachment, css, mjml, Attachment};
+use crate::{locale, view_renderer::ViewRenderer};
+
+/// The line width of plain-text bodies generated from HTML.
+const TEXT_WIDTH: usize = 78;
+
+/// The subject, the bodies and the attachments of an email.
+#[derive(Debug, Clone)]
+pub struct Content {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+    pub attachments: Vec<Attachment>,
+}
+
+impl Content {
+    /// Content with the plain-text body generated from the HTML body.
+    pub fn from_html(subject: impl Into<String>, html: String) -> Self {
+        let text = html_to_text(&html);
+        Self {
+            subject: subject.into(),
+            html,
+            text,
+            attachments: Vec::new(),
+        }
+    }
+
+    /// Attach the file at `path` of the assets directory.
+    pub fn attach(mut self, path: &str, content_type: &str) -> Result<Self> {
+        self.attachments.push(Attachment::file(path, content_type)?);
+        Ok(self)
+    }
+}
+
+/// Render the email template `key`, localized for `locale` if there are localized templates.
+pub fn render(
+    renderer: &ViewRenderer,
+    key: &str,
+    locale: Option<&str>,
+    data: impl Serialize,
+) -> Result<Content> {
+    let data = serde_json::to_value(data)?;
+    let keys = localized_keys(key, locale);
+    // The first key and extension a template exists for.
+    let resolve = |extensions: &[&'static str]| {
+        keys.iter()
+            .find_map(|key| {
+                extensions
+                    .iter()
+                    .find(|extension| renderer.contains(&format!("{key}{extension}")))
+                    .map(|extension| (key, *extension))
+            })
+            .unwrap_or((&keys[keys.len() - 1], extensions[extensions.len() - 1]))
+    };
+
+    let (subject_key, _) = resolve(&[".subject.txt"]);
+    let subject = renderer.render(&format!("{subject_key}.subject.txt"), &data)?;
+    // Line breaks are not allowed in subjects and are easily introduced by templates.
+    let subject = subject.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let (html_key, extension) = resolve(&[mjml::EXTENSION, ".html"]);
+    let name = format!("{html_key}{extension}");
+    let (html, attachments) = attachment::embedded_by(|| renderer.render(&name, &data));
+    let mut html = html?;
+    if extension == mjml::EXTENSION {
+        html = mjml::compile(&html)
+            .and_then(|html| css::inline_document(&html))
+            .with_context(|| format!("Compiling MJML template `{name}` failed"))?;
+    }
+
+    let text_key = format!("{html_key}.txt");
+    let text = if renderer.contains(&text_key) {
+        renderer.render(&text_key, &data)?
+    } else {
+        html_to_text(&html)
+    };
+
+    Ok(Content {
+        subject,
+        html,
+        text,
+        attachments,
+    })
+}
+
+/// The keys of the localized templates in lookup order, ending with `key` itself.
+fn localized_keys(key: &str, locale: Option<&str>) -> Vec<String> {
+    let mut keys: Vec<String> = locale
+        .map(locale::fallbacks)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|locale| match key.split_once('/') {
+            Some((dir, name)) => format!("{dir}/{locale}/{name}"),
+            None => format!("{locale}/{key}"),
+        })
+        .collect();
+    keys.push(key.into());
+    keys
+}
+
+/// The locales email templates are available in, taken from the localized subject templates.
+pub fn locales(renderer: &ViewRenderer) -> Vec<String> {
+    let mut locales: Vec<String> = renderer
+        .template_names()
+        .filter(|name| name.ends_with(".subject.txt"))
+        .filter_map(|name| {
+            let mut segments = name.split('/');
+            segments.next()?;
+            let locale = segments.next()?;
+            segments.next()?;
+            locale::normalize(locale).filter(|normalized| normalized == locale)
+        })
+        .collect();
+    locales.sort();
+    locales.dedup();
+    locales
+}
+
+/// Tables are read as a single column, emails use them for layout rather than for data.
+fn html_to_text(html: &str) -> String {
+    html2text::config::plain()
+        .raw_mode(true)
+        .string_from_read(html.as_bytes(), TEXT_WIDTH)
+        .expect("Failed to convert HTML to text")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use super::{locales, render};
+    use crate::{test_helper::TempDir, view_renderer::ViewRenderer};
+
+    #[test]
+    fn text_body_is_generated_if_missing() {
+        let dir = TempDir::new();
+        fs::create_dir_all(dir.join("emails")).unwrap();
+        fs::write(
+            dir.join("emails/hello.subject.txt"),
+            "Hello\n  {{ name }}\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("emails/hello.html"),
+            "<p>Hello <b>{{ name }}</b></p>",
+        )
+        .unwrap();
+        fs::write(dir.join("emails/welcome.subject.txt"), "Welcome").unwrap();
+        fs::write(dir.join("emails/welcome.html"), "<p>Welcome</p>").unwrap();
+        fs::write(dir.join("emails/welcome.txt"), "Welcome, {{ name }}!").unwrap();
+        fs::write(
+            dir.join("emails/branded.html"),
+            r#"<img src="{{ cid(path='static/logo.png') }}">"#,
+        )
+        .unwrap();
+        fs::write(dir.join("emails/branded.subject.txt"), "Branded").unwrap();
+
+        let renderer = ViewRenderer::from_dir(&dir).unwrap();
+        drop(dir);
+        let data = json! {{"name": "John & Jane"}};
+
+        let hello = render(&renderer, "emails/hello", None, &data).unwrap();
+        assert_eq!(hello.subject, "Hello John & Jane");
+        assert_eq!(hello.html, "<p>Hello <b>John &amp; Jane</b></p>");
+        assert_eq!(hello.text.trim(), "Hello John & Jane");
+
+        let welcome = render(&renderer, "emails/welcome", None, &data).unwrap();
+        assert_eq!(welcome.text, "Welcome, John & Jane!");
+        assert!(welcome.attachments.is_empty());
+
+        let branded = render(&renderer, "emails/branded", None, &data).unwrap();
+        assert_eq!(branded.html, r#"<img src="cid:static~logo.png@assets">"#);
+        assert_eq!(branded.attachments[0].path, "static/logo.png");
+
+        // Data can't attach files.
+        let data = json! {{"name": "cid:secret.png@assets"}};
+        let hello = render(&renderer, "emails/hello", None, &data).unwrap();
+        assert!(hello.html.contains("cid:secret.png@assets"));
+        assert!(hello.attachments.is_empty());
+    }
+
+    #[test]
+    fn localized_templates_fall_back_to_the_unlocalized_ones() {
+        let dir = TempDir::new();
+        fs::create_dir_all(dir.join("emails/de")).unwrap();
+        fs::create_dir_all(dir.join("emails/en-gb")).unwrap();
+        fs::write(dir.join("emails/hello.subject.txt"), "Hello").unwrap();
+        fs::write(dir.join("emails/hello.html"), "<p>Hello {{ name }}</p>").unwrap();
+        fs::write(dir.join("emails/hello.txt"), "Hello {{ name }}").unwrap();
+        fs::write(dir.join("emails/de/hello.subject.txt"), "Hallo").unwrap();
+        fs::write(dir.join("emails/de/hello.html"), "<p>Hallo {{ name }}</p>").unwrap();
+        fs::write(dir.join("emails/en-gb/hello.subject.txt"), "Hello, mate").unwrap();
+
+        let renderer = ViewRenderer::from_dir(&dir).unwrap();
+        drop(dir);
+        let data = json! {{"name": "John"}};
+
+        assert_eq!(locales(&renderer), ["de", "en-gb"]);
+
+        let de = render(&renderer, "emails/hello", Some("de-at"), &data).unwrap();
+        assert_eq!(de.subject, "Hallo");
+        assert_eq!(de.html, "<p>Hallo John</p>");
+        assert_eq!(de.text.trim(), "Hallo John");
+
+        let en_gb = render(&renderer, "emails/hello", Some("en-gb"), &data).unwrap();
+        assert_eq!(en_gb.subject, "Hello, mate");
+        assert_eq!(en_gb.text, "Hello John");
+
+        let fr = render(&renderer, "emails/hello", Some("fr"), &data).unwrap();
+        assert_eq!(fr.subject, "Hello");
+    }
+
+    #[test]
+    fn mjml_templates_are_compiled_and_inlined() {
+        let dir = TempDir::new();
+        fs::create_dir_all(dir.join("emails")).unwrap();
+        fs::write(dir.join("emails/hello.subject.txt"), "Hello").unwrap();
+        fs::write(
+            dir.join("emails/hello.mjml.html"),
+            "<mjml><mj-head><mj-style>.name { color: red; }</mj-style></mj-head>\
+             <mj-body><mj-section><mj-column>\
+             <mj-text>Hello <b class=\"name\">{{ name }}</b></mj-text>\
+             </mj-column></mj-section></mj-body></mjml>",
+        )
+        .unwrap();
+        fs::write(dir.join("emails/broken.subject.txt"), "Broken").unwrap();
+        fs::write(
+            dir.join("emails/broken.mjml.html"),
+            "<mjml><mj-body><mj-hero></mj-hero></mj-body></mjml>",
+        )
+        .unwrap();
+
+        let mut renderer = ViewRenderer::from_dir(&dir).unwrap();
+        let warnings = renderer.precompile_emails(&dir).unwrap();
+        drop(dir);
+        assert!(warnings.is_empty());
+        let data = json! {{"name": "John & Jane"}};
+
+        let hello = render(&renderer, "emails/hello", None, &data).unwrap();
+        assert!(hello
+            .html
+            .contains(r#"<b class="name" style="color: red;">John &amp; Jane</b>"#));
+        assert!(!hello.html.contains(".name {"));
+        // The media queries of the columns are kept, and not inlined.
+        assert!(hello
+            .html
+            .contains("@media only screen and (min-width:480px) { .mj-column-per-100 {"));
+        assert!(!hello.html.contains("!important;\""));
+        assert_eq!(hello.text.trim(), "Hello John & Jane");
+
+        let error = render(&renderer, "emails/broken", None, &data).unwrap_err();
+        assert_eq!(
+            format!("{error:#}"),
+            "Compiling MJML template `emails/broken.mjml.html` failed: \
+             Unsupported MJML element `<mj-hero>` on line 1"
+        );
+    }
+}
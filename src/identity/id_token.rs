@@ -5,6 +5,8 @@ use jwt::{DecodingKey, Validation};
 use serde::Deserialize;
 use url::Url;
 
+use crate::email::Address;
+
 /// A validated Id Token.
 #[derive(Debug)]
 pub struct IdToken {
@@ -20,6 +22,9 @@ pub struct IdToken {
 /// <https://openid.net/specs/openid-connect-core-1_0.html#StandardClaims>
 #[derive(Debug, Deserialize)]
 pub struct Claims {
+    /// The identifier of the user at the issuer.
+    #[serde(rename = "sub")]
+    pub subject: String,
     #[serde(flatten)]
     pub profile: Profile,
     #[serde(flatten)]
@@ -40,12 +45,14 @@ pub struct Profile {
     // From: Standard Claims:
     // > Its value is a JSON number representing the number of seconds from 1970-01-01T0:0:0Z as measured in UTC until the date/time.
     pub updated_at: DateTime<Utc>,
+    /// The user's locale as a BCP47 language tag, for example `de-AT`.
+    pub locale: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Email {
-    // TODO: verify: RFC 5322 [RFC5322] addr-spec syntax
-    pub email: String,
+    /// Tokens with an invalid address are rejected.
+    pub email: Address,
     pub email_verified: bool,
 }
 
@@ -1,41 +1,59 @@
-use std::{default, env, fs, path::PathBuf, sync::Arc};
+use std::{
+    env, fs,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
-use ::anyhow::{Context, Result};
+use ::anyhow::{bail, Context, Result};
 use axum::{
     extract::{FromRef, Query, State},
-    response::{IntoResponse, Redirect},
-    routing::{get, post},
-    Router,
+    middleware,
+    response::{IntoResponse, Redirect, Response},
+    routing::{delete, get, post, put},
+    Extension, Router,
 };
+use background::BackgroundTasks;
 use chrono::Utc;
 use jsonwebtoken as jwt;
 use jwt::jwk::JwkSet;
-use sea_orm::{Database, DatabaseConnection, EntityTrait};
+use lettre::message::Mailbox;
+use locale::AcceptLanguage;
+use sea_orm::{ConnectionTrait, Database, DatabaseConnection, EntityTrait};
 use serde::{Deserialize, Serialize};
 use serde_enum_str::{Deserialize_enum_str, Serialize_enum_str};
-use session::SessionStore;
-use tokio::task::JoinHandle;
+use session::{SessionIndex, SessionStore};
+use throttle::Throttle;
+use tokio::net::TcpListener;
 use tower_http::services::ServeDir;
-use tower_sessions::{cookie::time::Duration, Expiry, MemoryStore, SessionManagerLayer};
-use tower_sessions_redis_store::{fred::prelude::*, RedisStore};
+use tower_sessions::Session;
 use url::Url;
 use utoipa::OpenApi;
 use utoipa_scalar::{Scalar, Servable};
 
 use crate::id_token::IdToken;
-use user::users;
+use organization::invitations;
+use user::{authenticated, deletion, email_change, identities, impersonation, lockout, users};
 use view_renderer::*;
 
 mod anyhow;
 mod api;
+mod audit;
 mod auth0;
+mod background;
+mod client_ip;
 mod config;
-mod email;
+pub mod email;
 mod identity;
+mod locale;
+mod organization;
 pub mod respond;
 mod session;
 #[cfg(test)]
 mod test_helper;
+mod throttle;
+mod token;
 mod user;
 mod view_renderer;
 
@@ -45,14 +63,41 @@ pub use identity::*;
 #[derive(Debug)]
 pub struct StackZero {
     pub config: Config,
-    pub smtp_config: email::Config,
+    pub smtp_config: Option<email::Config>,
+    pub email_config: email::TransportConfig,
+    pub mailer: Arc<dyn email::Mailer>,
+    pub email_event_parsers: email::events::EventParsers,
+    /// The address emails are sent from.
+    pub email_from: Mailbox,
+    pub login_config: lockout::Config,
+    pub account_config: deletion::Config,
+    pub impersonation_config: impersonation::Config,
+    pub session_config: session::policy::Config,
+    pub proxy_config: Arc<client_ip::Config>,
     pub auth0: auth0::Config,
-    pub jwk_set: JwkSet,
+    /// The keys of the ID tokens, refreshed periodically so that Auth0 can rotate them.
+    pub jwk_set: RwLock<JwkSet>,
     pub session_store: SessionStore,
+    pub session_index: SessionIndex,
+    pub throttle: Throttle,
     pub db_connection: DatabaseConnection,
     pub template_renderer: ViewRenderer,
+    pub deletion_hooks: deletion::Hooks,
+    background_tasks: BackgroundTasks,
 }
 
+/// How often accounts with an elapsed deletion grace period are purged.
+const ACCOUNT_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often the email outbox is checked for emails that are due.
+const EMAIL_DELIVERY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the JWK set is downloaded again.
+const JWK_SET_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often expired sessions and throttle counters are deleted from the database.
+const SESSION_CLEANUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
 #[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
 pub enum Environment {
     #[default]
@@ -100,7 +145,17 @@ impl StackZero {
             toml::from_str(&file)?
         };
 
-        let template_renderer = ViewRenderer::from_dir(&config.template_dir)?;
+        let mut template_renderer = ViewRenderer::from_dir(&config.template_dir)?;
+        for warning in template_renderer.precompile_emails(&config.template_dir)? {
+            eprintln!("Email CSS warning: {warning}");
+        }
+
+        let (mailer, email_from) = stack_zero_conf.email.mailer(
+            stack_zero_conf.smtp.as_ref(),
+            &config.base_url,
+            config.environment,
+        )?;
+        let email_event_parsers = email::events::parsers(stack_zero_conf.email.sendgrid.as_ref())?;
 
         // TODO: load auth0 config from stack-zero.conf
 
@@ -111,19 +166,164 @@ impl StackZero {
 
         let database = Database::connect(env::var("DATABASE_URL")?).await?;
 
-        let session_store = SessionStore::from_env(config.environment).await?;
+        let session_store = SessionStore::new(
+            stack_zero_conf.session.backend(config.environment),
+            &stack_zero_conf.redis,
+            &database,
+        )
+        .await?;
+        let session_index = SessionIndex::for_session_store(&session_store);
+        let throttle = Throttle::for_session_store(&session_store);
 
         Ok(Self {
             config,
             smtp_config: stack_zero_conf.smtp,
+            email_config: stack_zero_conf.email,
+            mailer,
+            email_event_parsers,
+            email_from,
+            login_config: stack_zero_conf.login,
+            account_config: stack_zero_conf.account,
+            impersonation_config: stack_zero_conf.impersonation,
+            session_config: stack_zero_conf.session,
+            proxy_config: Arc::new(stack_zero_conf.proxies),
             auth0,
-            jwk_set,
+            jwk_set: RwLock::new(jwk_set),
             session_store,
+            session_index,
+            throttle,
             db_connection: database,
             template_renderer,
+            deletion_hooks: Default::default(),
+            background_tasks: Default::default(),
         })
     }
 
+    /// Register a hook that deletes the application's data of a user before the user's account
+    /// is deleted.
+    pub fn on_account_deletion(&mut self, hook: deletion::Hook) {
+        self.deletion_hooks.push(hook);
+    }
+
+    /// Start the background tasks, like purging deleted accounts and expired sessions.
+    ///
+    /// [`serve`](Self::serve) starts them, call this only when serving the application
+    /// otherwise. Starting them again has no effect.
+    pub fn start_background_tasks(self: &Arc<Self>) {
+        if !self.background_tasks.mark_started() {
+            return;
+        }
+
+        let stack_zero = self.clone();
+        self.background_tasks
+            .spawn_periodic("account purge", ACCOUNT_PURGE_INTERVAL, move || {
+                let stack_zero = stack_zero.clone();
+                async move {
+                    let purged = deletion::purge(
+                        &stack_zero.db_connection,
+                        &stack_zero.deletion_hooks,
+                        Utc::now().into(),
+                    )
+                    .await;
+                    match purged {
+                        Ok(0) => {}
+                        Ok(purged) => println!("Purged {purged} deleted accounts"),
+                        Err(e) => eprintln!("Purging deleted accounts failed: {e:?}"),
+                    }
+                }
+            });
+
+        let stack_zero = self.clone();
+        self.background_tasks.spawn_periodic(
+            "email delivery",
+            EMAIL_DELIVERY_INTERVAL,
+            move || {
+                let stack_zero = stack_zero.clone();
+                async move {
+                    if let Err(e) = stack_zero.deliver_emails().await {
+                        eprintln!("Delivering emails failed: {e:?}");
+                    }
+                }
+            },
+        );
+
+        let stack_zero = self.clone();
+        self.background_tasks.spawn_periodic(
+            "JWK set refresh",
+            JWK_SET_REFRESH_INTERVAL,
+            move || {
+                let stack_zero = stack_zero.clone();
+                async move {
+                    // The previous keys are kept if the download fails.
+                    match stack_zero.auth0.download_jwk_set().await {
+                        Ok(jwk_set) => *stack_zero.jwk_set.write().unwrap() = jwk_set,
+                        Err(e) => eprintln!("Refreshing the JWK set failed: {e:?}"),
+                    }
+                }
+            },
+        );
+
+        if let SessionStore::Database(store) = &self.session_store {
+            let store = store.clone();
+            self.background_tasks.spawn_periodic(
+                "session cleanup",
+                SESSION_CLEANUP_INTERVAL,
+                move || {
+                    let store = store.clone();
+                    async move {
+                        let now = Utc::now().into();
+                        if let Err(e) = store.delete_expired(now).await {
+                            eprintln!("Deleting expired sessions failed: {e:?}");
+                        }
+                        if let Err(e) = throttle::delete_expired(store.connection(), now).await {
+                            eprintln!("Deleting expired throttle counters failed: {e:?}");
+                        }
+                    }
+                },
+            );
+        }
+    }
+
+    /// Start the [background tasks](Self::start_background_tasks) and serve the application
+    /// until SIGTERM or SIGINT is received, then wait for the requests in flight and
+    /// [shut down](Self::shutdown).
+    pub async fn serve(self: &Arc<Self>, listener: TcpListener, router: Router) -> Result<()> {
+        self.start_background_tasks();
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+
+        self.shutdown().await
+    }
+
+    /// Stop the background tasks and close the connections to Redis and the database.
+    ///
+    /// All steps are run, even if some fail. Returns an error listing the failed ones.
+    pub async fn shutdown(&self) -> Result<()> {
+        let mut failures: Vec<String> = self
+            .background_tasks
+            .stop()
+            .await
+            .into_iter()
+            .map(|task| format!("Background task {task} failed"))
+            .collect();
+
+        if let Err(e) = self.session_store.close().await {
+            failures.push(format!("Closing the session store failed: {e:?}"));
+        }
+        if let Err(e) = self.db_connection.close_by_ref().await {
+            failures.push(format!("Closing the database connection failed: {e:?}"));
+        }
+
+        if !failures.is_empty() {
+            bail!("Shutdown failed:\n{}", failures.join("\n"));
+        }
+        Ok(())
+    }
+
     pub fn install_routes<State>(&self, router: Router<State>) -> Router<State>
     where
         Arc<StackZero>: FromRef<State>,
@@ -133,21 +333,203 @@ impl StackZero {
 
         // let session_store = MemoryStore::default();
 
-        let router = router
+        let mut router = router
             .route("/login", get(login))
             .route("/callback", get(callback))
             .nest_service("/static", static_files_service)
+            .route("/unlock", get(lockout::unlock))
+            .route("/invitations/accept", get(invitations::accept_link))
+            .route("/email-change/confirm", get(email_change::confirm))
+            .route("/account/deletion/confirm", get(deletion::confirm))
+            .route(impersonation::EXIT_PATH, post(impersonation::exit))
+            .route("/account/sessions", get(session::index::page))
+            .route("/admin/emails", get(email::outbox::page))
+            .route(
+                email::mailing_list::UNSUBSCRIBE_PATH,
+                get(email::mailing_list::page).post(email::mailing_list::one_click),
+            )
+            .route("/api/login", post(api::login))
+            .route("/api/email-change", post(api::change_email))
+            .route("/api/users/:user/impersonate", post(api::impersonate))
+            .route("/api/account/export", get(api::export_account))
+            .route(
+                "/api/sessions",
+                get(api::list_sessions).delete(api::revoke_other_sessions),
+            )
+            .route("/api/sessions/:session", delete(api::revoke_session))
+            .route(
+                "/api/account/deletion",
+                post(api::request_account_deletion).delete(api::cancel_account_deletion),
+            )
             .route("/api/sign-up", post(api::sign_up))
+            .route("/api/sign-up/complete", post(api::complete_sign_up))
+            .route("/api/organizations", post(api::create_organization))
+            .route(
+                "/api/organizations/:organization/invitations",
+                post(api::invite),
+            )
+            .route(
+                "/api/invitations/:invitation/resend",
+                post(api::resend_invitation),
+            )
+            .route(
+                "/api/invitations/:invitation",
+                delete(api::revoke_invitation),
+            )
+            .route("/api/admin/emails", get(api::list_emails))
+            .route("/api/admin/emails/:email/retry", post(api::retry_email))
+            .route("/api/email/events/:provider", post(email::events::webhook))
+            .route("/api/email/preferences", get(api::email_preferences))
+            .route(
+                "/api/email/preferences/:list",
+                put(api::update_email_preference),
+            )
             .merge(Scalar::with_url("/api", api::Doc::openapi()));
 
+        if self.config.environment == Environment::Development {
+            router = router
+                .route(email::preview::PATH, get(email::preview::index))
+                .route(
+                    &format!("{}/*name", email::preview::PATH),
+                    get(email::preview::show).post(email::preview::send),
+                );
+        }
+
+        let router = router
+            .layer(middleware::from_fn_with_state(
+                self.session_index.clone(),
+                session::index::track,
+            ))
+            .layer(middleware::from_fn_with_state(
+                self.session_config.clone(),
+                session::policy::enforce,
+            ));
+
         self.session_store
-            .add_layer(self.config.environment, router)
+            .add_layer(self.config.environment, &self.session_config, router)
+            .layer(Extension(self.proxy_config.clone()))
     }
 
     pub fn render(&self, key: &str, data: impl Serialize) -> Result<String> {
         self.template_renderer.render(key, data)
     }
 
+    /// Render a page template for the session.
+    ///
+    /// While an admin impersonates a user, the `impersonation` context variable contains the
+    /// information for a banner, see [`impersonation::Banner`].
+    pub async fn render_for_session(
+        &self,
+        session: &Session,
+        key: &str,
+        data: impl Serialize,
+    ) -> Result<String> {
+        let mut data = serde_json::to_value(data)?;
+        if let Some(banner) = impersonation::banner(session, &self.db_connection).await? {
+            let Some(data) = data.as_object_mut() else {
+                bail!("Template data must be an object");
+            };
+            data.insert("impersonation".into(), serde_json::to_value(banner)?);
+        }
+        self.render(key, data)
+    }
+
+    /// Render an email template in the recipient's `locale`, see [`email::render`].
+    ///
+    /// The CSS of the email templates is inlined when they are loaded, see [`email::css`].
+    pub fn render_email(
+        &self,
+        key: &str,
+        locale: Option<&str>,
+        data: impl Serialize,
+    ) -> Result<email::Content> {
+        email::render(&self.template_renderer, key, locale, data)
+    }
+
+    /// The locale of the email templates that matches the client's preferences best.
+    pub fn email_locale(&self, accept_language: &AcceptLanguage) -> Option<String> {
+        locale::negotiate(&accept_language.0, &email::locales(&self.template_renderer))
+    }
+
+    /// Send an email to `to`.
+    ///
+    /// The email is written to the outbox and delivered by the background worker.
+    pub async fn send_email(&self, to: &str, content: email::Content) -> Result<()> {
+        self.queue_email(&self.db_connection, to, content).await
+    }
+
+    /// Write an email to the outbox using `connection`.
+    ///
+    /// Pass a transaction to send the email only if the transaction is committed.
+    pub async fn queue_email(
+        &self,
+        connection: &impl ConnectionTrait,
+        to: &str,
+        content: email::Content,
+    ) -> Result<()> {
+        let email = email::Email::new(
+            self.email_from.clone(),
+            to,
+            content,
+            &self.config.template_dir,
+        )?;
+        email::outbox::enqueue(connection, &email, Utc::now().into()).await?;
+        Ok(())
+    }
+
+    /// Send an email of the mailing `list` to `user`, rendered from the email template `key` in the
+    /// user's locale. Returns `false` without sending if the user is not subscribed.
+    ///
+    /// The template data is extended with the `unsubscribe_url`, see [`email::mailing_list`].
+    pub async fn send_to_subscriber(
+        &self,
+        list: &entity::mailing_list::Model,
+        user: &entity::user::Model,
+        key: &str,
+        data: impl Serialize,
+    ) -> Result<bool> {
+        use email::mailing_list;
+
+        let connection = &self.db_connection;
+        let Some(subscription) = mailing_list::subscription(connection, list.id, user.id).await?
+        else {
+            return Ok(false);
+        };
+
+        let link = mailing_list::unsubscribe_link(&self.config.base_url, list.id, user.id)?;
+        let mut data = serde_json::to_value(data)?;
+        let Some(object) = data.as_object_mut() else {
+            bail!("Template data must be an object");
+        };
+        object.insert("unsubscribe_url".into(), link.as_str().into());
+
+        let content = self.render_email(key, user.locale.as_deref(), data)?;
+        let mut email = email::Email::new(
+            self.email_from.clone(),
+            &user.email,
+            content,
+            &self.config.template_dir,
+        )?;
+        email
+            .headers
+            .extend(mailing_list::headers(list, &link, &self.config.base_url));
+        email::outbox::enqueue_to_subscriber(connection, &email, &subscription, Utc::now().into())
+            .await?;
+        Ok(true)
+    }
+
+    /// Deliver the emails of the outbox that are due. Returns the number of delivered emails.
+    pub async fn deliver_emails(&self) -> Result<usize> {
+        email::outbox::deliver_due(
+            &self.db_connection,
+            self.mailer.as_ref(),
+            &self.email_config,
+            &self.config.template_dir,
+            Utc::now().into(),
+        )
+        .await
+    }
+
     pub async fn users(&self) -> Result<Vec<entity::user::Model>> {
         Ok(entity::user::Entity::find()
             .all(&self.db_connection)
@@ -155,6 +537,37 @@ impl StackZero {
     }
 }
 
+/// Resolves when SIGINT or, on Unix, SIGTERM is received.
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            eprintln!("Failed to listen for SIGINT: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                eprintln!("Failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {},
+        _ = terminate => {},
+    }
+    println!("Shutting down");
+}
+
 async fn login(State(state): State<Arc<StackZero>>) -> impl IntoResponse {
     // TODO: we can pre-create the full url in the configuration.
 
@@ -176,6 +589,9 @@ async fn login(State(state): State<Arc<StackZero>>) -> impl IntoResponse {
     Redirect::temporary(url.as_str())
 }
 
+/// The provider name of identities linked from auth0.
+const IDENTITY_PROVIDER: &str = "auth0";
+
 #[derive(Debug, Deserialize)]
 struct AuthCallbackQuery {
     // TODO: Add state here.
@@ -184,6 +600,7 @@ struct AuthCallbackQuery {
 
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
+#[allow(dead_code)]
 enum TokenResponse {
     Success {
         access_token: String,
@@ -223,15 +640,21 @@ enum TokenResponseError {
 ///
 /// <https://datatracker.ietf.org/doc/html/rfc6749#section-4.1.3>
 /// <https://auth0.com/docs/api/authentication#authorization-code-flow47>
-
 async fn callback(
     Query(query_params): Query<AuthCallbackQuery>,
     State(state): State<Arc<StackZero>>,
-) -> Result<impl IntoResponse, AppError> {
-    Ok(authorized(&query_params.code, &state).await?)
+    session: Session,
+) -> Result<Response, AppError> {
+    let user = authorized(&query_params.code, &state).await?;
+
+    let now = Utc::now().into();
+    authenticated::login(&session, &state.session_config, user.id, false).await?;
+    invitations::accept_pending(&session, &state.db_connection, &user, now).await;
+
+    respond::redirect("/")
 }
 
-async fn authorized(authorization_code: &str, config: &StackZero) -> Result<impl IntoResponse> {
+async fn authorized(authorization_code: &str, config: &StackZero) -> Result<entity::user::Model> {
     let auth0 = &config.auth0;
 
     let token_response = reqwest::Client::new()
@@ -256,32 +679,63 @@ async fn authorized(authorization_code: &str, config: &StackZero) -> Result<impl
             let token = IdToken::validate(
                 &(format!("https://{}/", config.auth0.domain)),
                 &config.auth0.client_id,
-                &config.jwk_set,
+                &config.jwk_set.read().unwrap(),
                 id_token,
             )?;
-            println!("Token successfully validated, inserting user");
+            println!("Token successfully validated");
             let connection = &config.db_connection;
             let claims = &token.claims;
-            let user = users::create(
+            if let Some(user) =
+                identities::find_user(connection, IDENTITY_PROVIDER, &claims.subject).await?
+            {
+                return Ok(user);
+            }
+
+            // Accounts are matched by email, an unverified address could belong to someone
+            // else.
+            if !claims.email.email_verified {
+                bail!(
+                    "Login with an unverified email address rejected: {}",
+                    claims.email.email
+                );
+            }
+
+            let now = Utc::now().into();
+            let user = match users::get_by_email(connection, &claims.email.email).await? {
+                Some(user) => user,
+                None => {
+                    let user = users::create(
+                        connection,
+                        &claims.profile.name,
+                        &claims.email.email,
+                        users::AuthenticationMethod::SingleSignOn,
+                        claims.profile.locale.as_deref().and_then(locale::normalize),
+                        now,
+                    )
+                    .await?;
+                    println!("User created with id: {}", user.id);
+                    user
+                }
+            };
+            identities::link(
                 connection,
-                &claims.profile.name,
-                &claims.email.email,
-                users::AuthenticationMethod::SingleSignOn,
-                Utc::now().into(),
+                user.id,
+                IDENTITY_PROVIDER,
+                &claims.subject,
+                claims.email.email.as_str(),
+                now,
             )
             .await?;
-            println!("User created with id: {}", user.id);
+            Ok(user)
         }
         TokenResponse::Error {
             error,
             error_description,
             error_uri,
-        } => {}
+        } => {
+            bail!("Token request failed: {error}: {error_description} ({error_uri:?})")
+        }
     }
-
-    println!("{token_response:?}");
-
-    Ok(())
 }
 
 #[cfg(test)]
@@ -0,0 +1,111 @@
+//! Locales of users.
+//!
+//! Locales are language tags normalized to lowercase with `-` separators, for example `de` or
+//! `de-at`. A locale falls back to its language, `de-at` to `de`.
+
+use std::convert::Infallible;
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+
+/// Normalize a language tag, `de_AT` becomes `de-at`. Returns `None` if it's not a valid tag.
+pub fn normalize(tag: &str) -> Option<String> {
+    let tag = tag.trim().replace('_', "-").to_ascii_lowercase();
+    let mut subtags = tag.split('-');
+
+    let language = subtags.next()?;
+    if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    if !subtags.all(|subtag| {
+        (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+    }) {
+        return None;
+    }
+    Some(tag)
+}
+
+/// The locale followed by its language, if it has a region or other subtags.
+pub fn fallbacks(locale: &str) -> Vec<&str> {
+    match locale.split_once('-') {
+        Some((language, _)) => vec![locale, language],
+        None => vec![locale],
+    }
+}
+
+/// The first of the preferred locales that is, or falls back to, one of the `available` ones.
+pub fn negotiate(preferred: &[String], available: &[String]) -> Option<String> {
+    preferred.iter().find_map(|locale| {
+        fallbacks(locale)
+            .into_iter()
+            .find(|candidate| available.iter().any(|a| a == candidate))
+            .map(Into::into)
+    })
+}
+
+/// The locales of the `Accept-Language` header, most preferred first.
+#[derive(Debug, Clone, Default)]
+pub struct AcceptLanguage(pub Vec<String>);
+
+impl AcceptLanguage {
+    pub fn parse(header: &str) -> Self {
+        let mut locales: Vec<(String, f32)> = header
+            .split(',')
+            .filter_map(|entry| {
+                let mut params = entry.split(';');
+                let locale = normalize(params.next()?)?;
+                let quality = params
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+                (quality > 0.0).then_some((locale, quality))
+            })
+            .collect();
+        // Stable, so locales with the same quality stay in the order of the header.
+        locales.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        Self(locales.into_iter().map(|(locale, _)| locale).collect())
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AcceptLanguage
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let header = parts
+            .headers
+            .get_all("accept-language")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+        Ok(Self::parse(&header))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{negotiate, normalize, AcceptLanguage};
+
+    #[test]
+    fn accept_language_is_ordered_by_quality() {
+        let AcceptLanguage(locales) =
+            AcceptLanguage::parse("fr;q=0.5, de_AT, en;q=0.8, *;q=0.1, es;q=0, x;q=1");
+        assert_eq!(locales, ["de-at", "en", "fr"]);
+        assert_eq!(normalize("zh-Hant-TW").unwrap(), "zh-hant-tw");
+        assert_eq!(normalize("german"), None);
+    }
+
+    #[test]
+    fn locales_fall_back_to_their_language() {
+        let available = vec!["de".to_string(), "en-gb".to_string()];
+        let negotiated = |preferred: &[&str]| {
+            let preferred: Vec<String> = preferred.iter().map(|l| l.to_string()).collect();
+            negotiate(&preferred, &available)
+        };
+        assert_eq!(negotiated(&["fr", "de-at", "en"]).unwrap(), "de");
+        assert_eq!(negotiated(&["en-gb"]).unwrap(), "en-gb");
+        assert_eq!(negotiated(&["en-us", "fr"]), None);
+    }
+}
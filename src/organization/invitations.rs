@@ -0,0 +1,516 @@
+//! Invitations of email addresses to organizations.
+//!
+//! An invitation email contains a signed link to `/invitations/accept`. If the invitee is not
+//! logged in, the invitation is remembered in the session and accepted after the login or
+//! sign-up.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    extract::{Query, State},
+    response::Response,
+};
+use chrono::{DateTime, Duration, FixedOffset};
+use entity::{invitation, organization_member::Role, user};
+use sea_orm::{
+    prelude::*, ActiveValue::Set, ConnectionTrait, DatabaseConnection, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tower_sessions::Session;
+use url::{form_urlencoded, Url};
+
+use super::organizations;
+use crate::{
+    email::{self, Address},
+    respond, token,
+    user::{authenticated, users},
+    AppError, StackZero,
+};
+
+// TODO: Add this to the configuration?
+const INVITATION_EXPIRATION: Duration = Duration::days(7);
+
+const PENDING_INVITATION_KEY: &str = "pending_invitation";
+
+/// Where invitees without an account are sent to.
+const SIGN_UP_PATH: &str = "/sign-up";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pending,
+    Accepted,
+    Revoked,
+    Expired,
+}
+
+pub fn status(invitation: &invitation::Model, now: DateTime<FixedOffset>) -> Status {
+    if invitation.accepted_date.is_some() {
+        Status::Accepted
+    } else if invitation.revoked_date.is_some() {
+        Status::Revoked
+    } else if invitation.expiration_date <= now {
+        Status::Expired
+    } else {
+        Status::Pending
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    invitation: Uuid,
+}
+
+/// Create a new invitation.
+///
+/// Fails if there is already a pending invitation for the email address.
+pub async fn create(
+    connection: &impl ConnectionTrait,
+    organization_id: Uuid,
+    email: &Address,
+    role: Role,
+    invited_by: Uuid,
+    date: DateTime<FixedOffset>,
+) -> Result<invitation::Model> {
+    let existing = invitation::Entity::find()
+        .filter(invitation::Column::OrganizationId.eq(organization_id))
+        .filter(users::email_matches(
+            invitation::Column::Email,
+            email.as_str(),
+        ))
+        .all(connection)
+        .await?;
+    if existing
+        .iter()
+        .any(|invitation| status(invitation, date) == Status::Pending)
+    {
+        bail!("There is already a pending invitation for {email}");
+    }
+
+    let new_invitation = invitation::Model {
+        id: Uuid::new_v4(),
+        organization_id,
+        email: email.to_string(),
+        role,
+        invited_by,
+        creation_date: date,
+        sent_date: date,
+        expiration_date: date + INVITATION_EXPIRATION,
+        accepted_date: None,
+        revoked_date: None,
+    };
+
+    invitation::Entity::insert(invitation::ActiveModel::from(new_invitation.clone()))
+        .exec(connection)
+        .await?;
+
+    Ok(new_invitation)
+}
+
+pub async fn get(
+    connection: &DatabaseConnection,
+    invitation_id: Uuid,
+) -> Result<Option<invitation::Model>> {
+    Ok(invitation::Entity::find_by_id(invitation_id)
+        .one(connection)
+        .await?)
+}
+
+/// Renew a pending invitation so that it can be sent again.
+///
+/// The expiration is extended, links sent out before stay valid until their own expiration.
+pub async fn renew(
+    connection: &impl ConnectionTrait,
+    invitation: invitation::Model,
+    date: DateTime<FixedOffset>,
+) -> Result<invitation::Model> {
+    if matches!(
+        status(&invitation, date),
+        Status::Accepted | Status::Revoked
+    ) {
+        bail!("Invitation can not be sent again");
+    }
+
+    let mut invitation = invitation::ActiveModel::from(invitation);
+    invitation.sent_date = Set(date);
+    invitation.expiration_date = Set(date + INVITATION_EXPIRATION);
+    Ok(invitation.update(connection).await?)
+}
+
+pub async fn revoke(
+    connection: &DatabaseConnection,
+    invitation: invitation::Model,
+    date: DateTime<FixedOffset>,
+) -> Result<invitation::Model> {
+    if status(&invitation, date) == Status::Accepted {
+        bail!("Invitation was already accepted");
+    }
+
+    let mut invitation = invitation::ActiveModel::from(invitation);
+    invitation.revoked_date = Set(Some(date));
+    Ok(invitation.update(connection).await?)
+}
+
+/// Accept the invitation on behalf of the user.
+///
+/// The invitation must be pending and addressed to the user's email.
+pub async fn accept(
+    connection: &DatabaseConnection,
+    invitation: invitation::Model,
+    user: &user::Model,
+    date: DateTime<FixedOffset>,
+) -> Result<()> {
+    if status(&invitation, date) != Status::Pending {
+        bail!("Invitation is not pending anymore");
+    }
+    if !invitation.email.eq_ignore_ascii_case(&user.email) {
+        bail!("Invitation was sent to another email address");
+    }
+
+    let txn = connection.begin().await?;
+
+    organizations::add_member(
+        &txn,
+        invitation.organization_id,
+        user.id,
+        invitation.role,
+        date,
+    )
+    .await?;
+
+    let mut invitation = invitation::ActiveModel::from(invitation);
+    invitation.accepted_date = Set(Some(date));
+    invitation.update(&txn).await?;
+
+    txn.commit().await?;
+
+    Ok(())
+}
+
+/// Accept an invitation that was remembered in the session before the user logged in or signed
+/// up.
+///
+/// The user is logged in already, so failures are only logged. The pending invitation is dropped
+/// either way.
+pub async fn accept_pending(
+    session: &Session,
+    connection: &DatabaseConnection,
+    user: &user::Model,
+    date: DateTime<FixedOffset>,
+) {
+    if let Err(e) = try_accept_pending(session, connection, user, date).await {
+        eprintln!(
+            "Accepting the pending invitation of user {} failed: {e:?}",
+            user.id
+        );
+        if let Err(e) = session.remove_value(PENDING_INVITATION_KEY).await {
+            eprintln!("Removing the pending invitation from the session failed: {e:?}");
+        }
+    }
+}
+
+async fn try_accept_pending(
+    session: &Session,
+    connection: &DatabaseConnection,
+    user: &user::Model,
+    date: DateTime<FixedOffset>,
+) -> Result<()> {
+    let Some(invitation_id) = session.remove::<Uuid>(PENDING_INVITATION_KEY).await? else {
+        return Ok(());
+    };
+    let Some(invitation) = get(connection, invitation_id).await? else {
+        return Ok(());
+    };
+    accept(connection, invitation, user, date).await
+}
+
+/// The signed link to accept the invitation.
+pub fn link(base_url: &Url, invitation: &invitation::Model) -> Result<Url> {
+    let expiration = (invitation.expiration_date - invitation.sent_date).to_std()?;
+    let token = token::sign(
+        Claims {
+            invitation: invitation.id,
+        },
+        expiration,
+    )?;
+    let mut link = base_url.join("invitations/accept")?;
+    link.query_pairs_mut().append_pair("t", &token);
+    Ok(link)
+}
+
+/// Render the invitation email.
+///
+/// It's sent in the locale of the invitee if they are registered already, otherwise in the
+/// locale of the inviter.
+pub async fn render_email(
+    state: &StackZero,
+    invitation: &invitation::Model,
+) -> Result<email::Content> {
+    let connection = &state.db_connection;
+    let organization = organizations::get(connection, invitation.organization_id)
+        .await?
+        .context("Organization not found")?;
+    let inviter = user::Entity::find_by_id(invitation.invited_by)
+        .one(connection)
+        .await?
+        .context("Inviting user not found")?;
+    let invitee = match invitation.email.parse::<Address>() {
+        Ok(email) => users::get_by_email(connection, &email).await?,
+        Err(_) => None,
+    };
+    let locale = invitee
+        .and_then(|invitee| invitee.locale)
+        .or(inviter.locale);
+
+    let site = &state.config.base_url;
+    let link = link(site, invitation)?;
+
+    state.render_email(
+        "emails/organization_invitation",
+        locale.as_deref(),
+        json! {{
+            "site": site,
+            "organization": organization.name,
+            "inviter": inviter.name,
+            "email": invitation.email,
+            "link": link,
+            "expiration_date": invitation.expiration_date
+        }},
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcceptQuery {
+    t: String,
+}
+
+/// The handler for the link in the invitation email.
+pub async fn accept_link(
+    State(state): State<Arc<StackZero>>,
+    Query(query): Query<AcceptQuery>,
+    session: Session,
+) -> Result<Response, AppError> {
+    let claims: Claims = token::verify(&query.t)?;
+    let connection = &state.db_connection;
+    let now = chrono::Utc::now().into();
+
+    let invitation = get(connection, claims.invitation)
+        .await?
+        .context("Invitation not found")?;
+    if status(&invitation, now) != Status::Pending {
+        return Err(anyhow!("Invitation is not valid anymore").into());
+    }
+
+    match authenticated::current(&session, connection).await? {
+        // Admins must not join organizations on behalf of the users they impersonate.
+        Some((_, true)) => return Ok(authenticated::impersonation_forbidden()),
+        Some((user, false)) => {
+            accept(connection, invitation, &user, now).await?;
+            return respond::redirect("/");
+        }
+        None => {}
+    }
+
+    session
+        .insert(PENDING_INVITATION_KEY, invitation.id)
+        .await?;
+
+    let registered = match invitation.email.parse::<Address>() {
+        Ok(email) => users::get_by_email(connection, &email).await?.is_some(),
+        Err(_) => false,
+    };
+    if registered {
+        respond::redirect("/login")
+    } else {
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("email", &invitation.email)
+            .finish();
+        respond::redirect(&format!("{SIGN_UP_PATH}?{query}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{Duration, SubsecRound, Utc};
+    use entity::{invitation, organization_member::Role, user};
+    use sea_orm::{prelude::Uuid, DatabaseConnection};
+    use tower_sessions::{MemoryStore, Session};
+
+    use super::{
+        accept, accept_pending, create, renew, revoke, status, Status, INVITATION_EXPIRATION,
+        PENDING_INVITATION_KEY,
+    };
+    use crate::{organization::organizations, test_helper};
+
+    #[test]
+    fn status_follows_the_dates() {
+        let now = Utc::now().into();
+        let pending = invitation::Model {
+            id: Uuid::new_v4(),
+            organization_id: Uuid::new_v4(),
+            email: "jane@doe.com".into(),
+            role: Role::Member,
+            invited_by: Uuid::new_v4(),
+            creation_date: now,
+            sent_date: now,
+            expiration_date: now + INVITATION_EXPIRATION,
+            accepted_date: None,
+            revoked_date: None,
+        };
+        assert_eq!(status(&pending, now), Status::Pending);
+        assert_eq!(status(&pending, pending.expiration_date), Status::Expired);
+
+        let accepted = invitation::Model {
+            accepted_date: Some(now),
+            ..pending.clone()
+        };
+        assert_eq!(status(&accepted, now), Status::Accepted);
+        assert_eq!(
+            status(&accepted, accepted.expiration_date),
+            Status::Accepted
+        );
+
+        let revoked = invitation::Model {
+            revoked_date: Some(now),
+            ..pending
+        };
+        assert_eq!(status(&revoked, now), Status::Revoked);
+    }
+
+    /// An organization of `owner@example.com` and the invitation of `invitee` to it.
+    async fn invitation(
+        connection: &DatabaseConnection,
+        invitee: &str,
+    ) -> (invitation::Model, user::Model) {
+        let owner = test_helper::user(connection, "owner@example.com")
+            .await
+            .unwrap();
+        let organization = organizations::create(connection, "Acme", owner.id, Utc::now().into())
+            .await
+            .unwrap();
+        let invitation = create(
+            connection,
+            organization.id,
+            &invitee.parse().unwrap(),
+            Role::Member,
+            owner.id,
+            // Dates are stored with microseconds.
+            Utc::now().trunc_subsecs(0).into(),
+        )
+        .await
+        .unwrap();
+        (invitation, owner)
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Docker"]
+    async fn invitations_are_accepted_by_their_invitee() {
+        let connection = test_helper::database().await.unwrap();
+        let (invitation, _) = invitation(&connection, "Jane@Doe.com").await;
+        let jane = test_helper::user(&connection, "jane@doe.com")
+            .await
+            .unwrap();
+        let john = test_helper::user(&connection, "john@doe.com")
+            .await
+            .unwrap();
+        let now = Utc::now().into();
+
+        // Only one pending invitation per address.
+        assert!(create(
+            &connection,
+            invitation.organization_id,
+            &"jane@doe.com".parse().unwrap(),
+            Role::Admin,
+            invitation.invited_by,
+            now,
+        )
+        .await
+        .is_err());
+
+        assert!(accept(&connection, invitation.clone(), &john, now)
+            .await
+            .is_err());
+        let expired = invitation.expiration_date + Duration::seconds(1);
+        assert!(accept(&connection, invitation.clone(), &jane, expired)
+            .await
+            .is_err());
+
+        accept(&connection, invitation.clone(), &jane, now)
+            .await
+            .unwrap();
+        assert_eq!(
+            organizations::role_of(&connection, invitation.organization_id, jane.id)
+                .await
+                .unwrap(),
+            Some(Role::Member)
+        );
+        let accepted = super::get(&connection, invitation.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(status(&accepted, now), Status::Accepted);
+
+        // Accepted invitations can't be accepted, renewed or revoked.
+        assert!(accept(&connection, accepted.clone(), &jane, now)
+            .await
+            .is_err());
+        assert!(renew(&connection, accepted.clone(), now).await.is_err());
+        assert!(revoke(&connection, accepted, now).await.is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Docker"]
+    async fn invitations_are_renewed_and_revoked() {
+        let connection = test_helper::database().await.unwrap();
+        let (invitation, _) = invitation(&connection, "jane@doe.com").await;
+        let jane = test_helper::user(&connection, "jane@doe.com")
+            .await
+            .unwrap();
+
+        // Expired invitations can be sent again.
+        let later = invitation.expiration_date + Duration::days(1);
+        assert_eq!(status(&invitation, later), Status::Expired);
+        let renewed = renew(&connection, invitation, later).await.unwrap();
+        assert_eq!(renewed.sent_date, later);
+        assert_eq!(renewed.expiration_date, later + INVITATION_EXPIRATION);
+        assert_eq!(status(&renewed, later), Status::Pending);
+
+        let revoked = revoke(&connection, renewed, later).await.unwrap();
+        assert_eq!(status(&revoked, later), Status::Revoked);
+        assert!(accept(&connection, revoked.clone(), &jane, later)
+            .await
+            .is_err());
+        assert!(renew(&connection, revoked, later).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn failing_pending_invitations_are_dropped() {
+        let session = Session::new(None, Arc::new(MemoryStore::default()), None);
+        session
+            .insert(PENDING_INVITATION_KEY, "not an invitation id")
+            .await
+            .unwrap();
+        let user = user::Model {
+            id: Uuid::new_v4(),
+            name: "Invitee".into(),
+            email: "invitee@example.com".into(),
+            creation_date: Utc::now().into(),
+            password: String::new(),
+            deletion_date: None,
+            admin: false,
+            locale: None,
+        };
+
+        // Fails before the database is queried.
+        let connection = DatabaseConnection::Disconnected;
+        accept_pending(&session, &connection, &user, Utc::now().into()).await;
+
+        assert_eq!(
+            session.get_value(PENDING_INVITATION_KEY).await.unwrap(),
+            None
+        );
+    }
+}
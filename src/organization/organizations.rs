@@ -0,0 +1,97 @@
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset};
+use entity::{organization, organization_member, organization_member::Role};
+use sea_orm::{
+    prelude::*, sea_query::OnConflict, ConnectionTrait, DatabaseConnection, TransactionTrait,
+};
+
+/// Create a new organization with `owner` as its first admin.
+pub async fn create(
+    connection: &DatabaseConnection,
+    name: &str,
+    owner: Uuid,
+    date: DateTime<FixedOffset>,
+) -> Result<organization::Model> {
+    let new_organization = organization::Model {
+        id: Uuid::new_v4(),
+        name: name.into(),
+        creation_date: date,
+    };
+
+    {
+        let txn = connection.begin().await?;
+
+        organization::Entity::insert(organization::ActiveModel::from(new_organization.clone()))
+            .exec(&txn)
+            .await?;
+
+        add_member(&txn, new_organization.id, owner, Role::Admin, date).await?;
+
+        txn.commit().await?;
+    }
+
+    Ok(new_organization)
+}
+
+pub async fn get(
+    connection: &DatabaseConnection,
+    organization_id: Uuid,
+) -> Result<Option<organization::Model>> {
+    Ok(organization::Entity::find_by_id(organization_id)
+        .one(connection)
+        .await?)
+}
+
+/// Add a user to an organization. If the user is already a member, the existing membership is
+/// left as is.
+pub async fn add_member(
+    connection: &impl ConnectionTrait,
+    organization_id: Uuid,
+    user_id: Uuid,
+    role: Role,
+    date: DateTime<FixedOffset>,
+) -> Result<()> {
+    let member = organization_member::Model {
+        organization_id,
+        user_id,
+        role,
+        creation_date: date,
+    };
+
+    organization_member::Entity::insert(organization_member::ActiveModel::from(member))
+        .on_conflict(
+            OnConflict::columns([
+                organization_member::Column::OrganizationId,
+                organization_member::Column::UserId,
+            ])
+            .do_nothing()
+            .to_owned(),
+        )
+        .do_nothing()
+        .exec(connection)
+        .await?;
+
+    Ok(())
+}
+
+/// The role of the user in the organization, `None` if the user is not a member.
+pub async fn role_of(
+    connection: &DatabaseConnection,
+    organization_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<Role>> {
+    Ok(
+        organization_member::Entity::find_by_id((organization_id, user_id))
+            .one(connection)
+            .await?
+            .map(|member| member.role),
+    )
+}
+
+pub async fn is_admin(
+    connection: &DatabaseConnection,
+    organization_id: Uuid,
+    user_id: Uuid,
+) -> Result<bool> {
+    Ok(role_of(connection, organization_id, user_id).await? == Some(Role::Admin))
+}
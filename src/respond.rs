@@ -1,5 +1,4 @@
 use axum::response::{Html, IntoResponse, Redirect, Response};
-use serde::Serialize;
 
 use crate::AppError;
 
@@ -0,0 +1,129 @@
+//! A session store in the application's database, for deployments without Redis.
+//!
+//! Expired sessions are not returned, but stay in the table until [`DatabaseStore::delete_expired`]
+//! removes them, together with their entries in the session index.
+
+use anyhow::Result;
+use axum::async_trait;
+use chrono::{DateTime, FixedOffset, Utc};
+use entity::{session, session_device};
+use sea_orm::{prelude::*, sea_query::Query, ActiveValue::Set, DatabaseConnection, SqlErr};
+use tower_sessions::{
+    cookie::time::OffsetDateTime,
+    session::{Id, Record},
+    session_store::{self, SessionStore},
+};
+
+#[derive(Debug, Clone)]
+pub struct DatabaseStore {
+    connection: DatabaseConnection,
+}
+
+impl DatabaseStore {
+    pub fn new(connection: DatabaseConnection) -> Self {
+        Self { connection }
+    }
+
+    /// The connection the sessions are stored with.
+    pub fn connection(&self) -> &DatabaseConnection {
+        &self.connection
+    }
+
+    /// Delete the sessions that expired before `date`. Returns the number of deleted sessions.
+    pub async fn delete_expired(&self, date: DateTime<FixedOffset>) -> Result<u64> {
+        session_device::Entity::delete_many()
+            .filter(
+                session_device::Column::SessionId.in_subquery(
+                    Query::select()
+                        .column(session::Column::Id)
+                        .from(session::Entity)
+                        .and_where(session::Column::ExpiryDate.lte(date))
+                        .to_owned(),
+                ),
+            )
+            .exec(&self.connection)
+            .await?;
+
+        let result = session::Entity::delete_many()
+            .filter(session::Column::ExpiryDate.lte(date))
+            .exec(&self.connection)
+            .await?;
+        Ok(result.rows_affected)
+    }
+
+    fn active_model(record: &Record) -> session_store::Result<session::ActiveModel> {
+        let data = serde_json::to_value(&record.data)
+            .map_err(|e| session_store::Error::Encode(e.to_string()))?;
+        Ok(session::ActiveModel {
+            id: Set(record.id.to_string()),
+            data: Set(data),
+            expiry_date: Set(to_chrono(record.expiry_date)?),
+        })
+    }
+}
+
+#[async_trait]
+impl SessionStore for DatabaseStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        loop {
+            match Self::active_model(record)?.insert(&self.connection).await {
+                Ok(_) => return Ok(()),
+                Err(e) if matches!(e.sql_err(), Some(SqlErr::UniqueConstraintViolation(_))) => {
+                    record.id = Id::default();
+                }
+                Err(e) => return Err(backend(e)),
+            }
+        }
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        // Like the Redis store, sessions deleted in the meantime are not recreated.
+        session::Entity::update_many()
+            .set(Self::active_model(record)?)
+            .filter(session::Column::Id.eq(record.id.to_string()))
+            .exec(&self.connection)
+            .await
+            .map_err(backend)?;
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let Some(session) = session::Entity::find_by_id(session_id.to_string())
+            .filter(session::Column::ExpiryDate.gt(Utc::now()))
+            .one(&self.connection)
+            .await
+            .map_err(backend)?
+        else {
+            return Ok(None);
+        };
+
+        let data = serde_json::from_value(session.data)
+            .map_err(|e| session_store::Error::Decode(e.to_string()))?;
+        let expiry_date = OffsetDateTime::from_unix_timestamp(session.expiry_date.timestamp())
+            .map_err(|e| session_store::Error::Decode(e.to_string()))?;
+
+        Ok(Some(Record {
+            id: *session_id,
+            data,
+            expiry_date,
+        }))
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        session::Entity::delete_by_id(session_id.to_string())
+            .exec(&self.connection)
+            .await
+            .map_err(backend)?;
+        Ok(())
+    }
+}
+
+fn to_chrono(date: OffsetDateTime) -> session_store::Result<DateTime<FixedOffset>> {
+    DateTime::from_timestamp(date.unix_timestamp(), 0)
+        .map(Into::into)
+        .ok_or_else(|| session_store::Error::Encode(format!("Expiry date out of range: {date}")))
+}
+
+fn backend(e: DbErr) -> session_store::Error {
+    session_store::Error::Backend(e.to_string())
+}
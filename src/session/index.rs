@@ -0,0 +1,412 @@
+//! The sessions users are logged in to.
+//!
+//! Every session of a logged in user is recorded with the device it is used from. The index is
+//! kept in the same backend as the sessions, in the `session_device` table for the database
+//! backend. Entries of sessions that expired are removed when the sessions of a user are listed.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use axum::{
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::{Html, Response},
+};
+use chrono::{DateTime, Utc};
+use entity::session_device;
+use sea_orm::{prelude::*, sea_query::OnConflict, DatabaseConnection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tower_sessions::Session;
+use tower_sessions_redis_store::fred::prelude::*;
+
+use super::SessionStore;
+use crate::{
+    client_ip::ClientIp,
+    user::authenticated::{self, Authenticated},
+    AppError, StackZero,
+};
+
+const KEY_PREFIX: &str = "user-sessions:";
+
+/// Minimum seconds between two updates of the last seen date of a session.
+const TOUCH_INTERVAL: i64 = 60;
+
+/// The index of a user expires in Redis if none of the user's sessions was used for this long.
+const REDIS_EXPIRATION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The device a session is used from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Device {
+    pub user_agent: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub creation_date: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ActiveSession {
+    pub id: String,
+    #[serde(flatten)]
+    pub device: Device,
+    /// This is the session of the request.
+    pub current: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum SessionIndex {
+    Memory(Arc<Mutex<HashMap<Uuid, HashMap<String, Device>>>>),
+    Database(DatabaseConnection),
+    Redis { pool: RedisPool, key_prefix: String },
+}
+
+impl SessionIndex {
+    pub fn memory() -> Self {
+        Self::Memory(Default::default())
+    }
+
+    /// Use the same backend the sessions are stored in.
+    pub fn for_session_store(session_store: &SessionStore) -> Self {
+        match session_store {
+            SessionStore::Memory(_) => Self::memory(),
+            SessionStore::Database(store) => Self::Database(store.connection().clone()),
+            SessionStore::Redis {
+                pool, key_prefix, ..
+            } => Self::Redis {
+                pool: pool.clone(),
+                key_prefix: format!("{key_prefix}{KEY_PREFIX}"),
+            },
+        }
+    }
+
+    /// Record that the session of the user was used from a device now.
+    ///
+    /// The last seen date is updated at most once per minute, unless the device changes.
+    pub async fn touch(
+        &self,
+        user: Uuid,
+        session: &str,
+        user_agent: Option<String>,
+        ip: Option<IpAddr>,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        let known = self.get(user, session).await?;
+        let creation_date = match known {
+            Some(device)
+                if device.user_agent == user_agent
+                    && device.ip == ip
+                    && (now - device.last_seen).num_seconds() < TOUCH_INTERVAL =>
+            {
+                return Ok(());
+            }
+            Some(device) => device.creation_date,
+            None => now,
+        };
+
+        let device = Device {
+            user_agent,
+            ip,
+            creation_date,
+            last_seen: now,
+        };
+        self.put(user, session, &device).await
+    }
+
+    pub async fn get(&self, user: Uuid, session: &str) -> Result<Option<Device>> {
+        match self {
+            SessionIndex::Memory(index) => Ok(index
+                .lock()
+                .unwrap()
+                .get(&user)
+                .and_then(|sessions| sessions.get(session))
+                .cloned()),
+            SessionIndex::Database(connection) => session_device::Entity::find_by_id(session)
+                .filter(session_device::Column::UserId.eq(user))
+                .one(connection)
+                .await?
+                .map(|entry| Ok(serde_json::from_value(entry.device)?))
+                .transpose(),
+            SessionIndex::Redis { pool, key_prefix } => {
+                let device: Option<String> =
+                    pool.hget(redis_key(key_prefix, user), session).await?;
+                Ok(device.map(|d| serde_json::from_str(&d)).transpose()?)
+            }
+        }
+    }
+
+    /// All sessions of the user in the index, including expired ones.
+    pub async fn all(&self, user: Uuid) -> Result<Vec<(String, Device)>> {
+        match self {
+            SessionIndex::Memory(index) => Ok(index
+                .lock()
+                .unwrap()
+                .get(&user)
+                .map(|sessions| {
+                    sessions
+                        .iter()
+                        .map(|(id, device)| (id.clone(), device.clone()))
+                        .collect()
+                })
+                .unwrap_or_default()),
+            SessionIndex::Database(connection) => session_device::Entity::find()
+                .filter(session_device::Column::UserId.eq(user))
+                .all(connection)
+                .await?
+                .into_iter()
+                .map(|entry| Ok((entry.session_id, serde_json::from_value(entry.device)?)))
+                .collect(),
+            SessionIndex::Redis { pool, key_prefix } => {
+                let sessions: HashMap<String, String> =
+                    pool.hgetall(redis_key(key_prefix, user)).await?;
+                sessions
+                    .into_iter()
+                    .map(|(id, device)| Ok((id, serde_json::from_str(&device)?)))
+                    .collect()
+            }
+        }
+    }
+
+    async fn put(&self, user: Uuid, session: &str, device: &Device) -> Result<()> {
+        match self {
+            SessionIndex::Memory(index) => {
+                index
+                    .lock()
+                    .unwrap()
+                    .entry(user)
+                    .or_default()
+                    .insert(session.into(), device.clone());
+            }
+            SessionIndex::Database(connection) => {
+                let entry = session_device::Model {
+                    session_id: session.into(),
+                    user_id: user,
+                    device: serde_json::to_value(device)?,
+                };
+                session_device::Entity::insert(session_device::ActiveModel::from(entry))
+                    .on_conflict(
+                        OnConflict::column(session_device::Column::SessionId)
+                            .update_columns([
+                                session_device::Column::UserId,
+                                session_device::Column::Device,
+                            ])
+                            .to_owned(),
+                    )
+                    .exec(connection)
+                    .await?;
+            }
+            SessionIndex::Redis { pool, key_prefix } => {
+                let key = redis_key(key_prefix, user);
+                pool.hset::<(), _, _>(&key, (session, serde_json::to_string(device)?))
+                    .await?;
+                pool.expire::<(), _>(&key, REDIS_EXPIRATION.as_secs() as i64)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn remove(&self, user: Uuid, session: &str) -> Result<()> {
+        match self {
+            SessionIndex::Memory(index) => {
+                let mut index = index.lock().unwrap();
+                if let Some(sessions) = index.get_mut(&user) {
+                    sessions.remove(session);
+                    if sessions.is_empty() {
+                        index.remove(&user);
+                    }
+                }
+            }
+            SessionIndex::Database(connection) => {
+                session_device::Entity::delete_many()
+                    .filter(session_device::Column::SessionId.eq(session))
+                    .filter(session_device::Column::UserId.eq(user))
+                    .exec(connection)
+                    .await?;
+            }
+            SessionIndex::Redis { pool, key_prefix } => {
+                pool.hdel::<(), _, _>(redis_key(key_prefix, user), session)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn redis_key(prefix: &str, user: Uuid) -> String {
+    format!("{prefix}{user}")
+}
+
+/// The active sessions of the user, most recently used first.
+///
+/// `current` is the id of the session of the request.
+pub async fn list(
+    store: &SessionStore,
+    index: &SessionIndex,
+    user: Uuid,
+    current: Option<&str>,
+) -> Result<Vec<ActiveSession>> {
+    let mut active = Vec::new();
+    for (id, device) in index.all(user).await? {
+        if !store.exists(&id).await? {
+            index.remove(user, &id).await?;
+            continue;
+        }
+        active.push(ActiveSession {
+            current: current == Some(id.as_str()),
+            id,
+            device,
+        });
+    }
+    active.sort_by_key(|session| std::cmp::Reverse(session.device.last_seen));
+    Ok(active)
+}
+
+/// Revoke a session of the user. Returns `false` if the session does not belong to the user.
+pub async fn revoke(
+    store: &SessionStore,
+    index: &SessionIndex,
+    user: Uuid,
+    session: &str,
+) -> Result<bool> {
+    if index.get(user, session).await?.is_none() {
+        return Ok(false);
+    }
+    store.delete(session).await?;
+    index.remove(user, session).await?;
+    Ok(true)
+}
+
+/// Revoke all sessions of the user except `current`. Returns the number of revoked sessions.
+pub async fn revoke_others(
+    store: &SessionStore,
+    index: &SessionIndex,
+    user: Uuid,
+    current: Option<&str>,
+) -> Result<usize> {
+    let mut revoked = 0;
+    for (id, _) in index.all(user).await? {
+        if current == Some(id.as_str()) {
+            continue;
+        }
+        store.delete(&id).await?;
+        index.remove(user, &id).await?;
+        revoked += 1;
+    }
+    Ok(revoked)
+}
+
+/// The page listing the sessions of the current user.
+pub async fn page(
+    State(state): State<Arc<StackZero>>,
+    Authenticated(user): Authenticated,
+    session: Session,
+) -> Result<Html<String>, AppError> {
+    let current = session.id().map(|id| id.to_string());
+    let sessions = list(
+        &state.session_store,
+        &state.session_index,
+        user.id,
+        current.as_deref(),
+    )
+    .await?;
+
+    Ok(Html(
+        state
+            .render_for_session(
+                &session,
+                "account/sessions",
+                json! {{"name": user.name, "sessions": sessions}},
+            )
+            .await?,
+    ))
+}
+
+/// Middleware that records the sessions of logged in users in the index.
+///
+/// Runs after the handler, so that sessions a user just logged in to are recorded, too.
+pub async fn track(
+    State(index): State<SessionIndex>,
+    ClientIp(ip): ClientIp,
+    session: Session,
+    request: Request,
+    next: Next,
+) -> Response {
+    let user_agent = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+
+    let response = next.run(request).await;
+
+    if let Err(e) = record(&index, &session, user_agent, ip).await {
+        eprintln!("Failed to record session: {e:?}");
+    }
+
+    response
+}
+
+async fn record(
+    index: &SessionIndex,
+    session: &Session,
+    user_agent: Option<String>,
+    ip: Option<IpAddr>,
+) -> Result<()> {
+    let Some(user) = authenticated::user_id(session).await? else {
+        return Ok(());
+    };
+
+    // New sessions get their id when they are saved.
+    if session.id().is_none() {
+        session.save().await?;
+    }
+    let Some(id) = session.id() else {
+        return Ok(());
+    };
+
+    index
+        .touch(user, &id.to_string(), user_agent, ip, Utc::now())
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use sea_orm::prelude::Uuid;
+
+    use super::SessionIndex;
+
+    #[tokio::test]
+    async fn touch_keeps_creation_date_and_throttles_updates() {
+        let index = SessionIndex::memory();
+        let user = Uuid::new_v4();
+        let created = Utc::now();
+
+        index.touch(user, "a", None, None, created).await.unwrap();
+        let later = created + Duration::seconds(10);
+        index.touch(user, "a", None, None, later).await.unwrap();
+        assert_eq!(
+            index.get(user, "a").await.unwrap().unwrap().last_seen,
+            created
+        );
+
+        let much_later = created + Duration::minutes(5);
+        index
+            .touch(user, "a", Some("agent".into()), None, much_later)
+            .await
+            .unwrap();
+        let device = index.get(user, "a").await.unwrap().unwrap();
+        assert_eq!(device.creation_date, created);
+        assert_eq!(device.last_seen, much_later);
+        assert_eq!(device.user_agent.as_deref(), Some("agent"));
+
+        index.remove(user, "a").await.unwrap();
+        assert!(index.all(user).await.unwrap().is_empty());
+    }
+}
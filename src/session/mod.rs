@@ -0,0 +1,166 @@
+use std::sync::Mutex;
+
+use anyhow::Result;
+use axum::Router;
+use sea_orm::DatabaseConnection;
+use serde::Deserialize;
+use tokio::task::JoinHandle;
+use tower_sessions::{session::Id, session_store::SessionStore as _, MemoryStore};
+use tower_sessions_redis_store::fred::prelude::*;
+
+use crate::Environment;
+
+pub mod database;
+pub mod index;
+pub mod policy;
+pub mod redis;
+
+pub use index::SessionIndex;
+
+/// Where sessions are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Memory,
+    Redis,
+    Database,
+}
+
+impl Backend {
+    /// Memory in development, Redis in production.
+    pub fn for_environment(environment: Environment) -> Self {
+        match environment {
+            Environment::Development => Self::Memory,
+            Environment::Production => Self::Redis,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SessionStore {
+    Memory(MemoryStore),
+    Database(database::DatabaseStore),
+    Redis {
+        pool: RedisPool,
+        /// Taken when the store is closed.
+        connection: Mutex<Option<JoinHandle<Result<(), RedisError>>>>,
+        /// Prefix of all keys the application stores in Redis.
+        key_prefix: String,
+    },
+}
+
+impl SessionStore {
+    pub async fn new(
+        backend: Backend,
+        redis: &redis::Config,
+        database: &DatabaseConnection,
+    ) -> Result<Self> {
+        match backend {
+            Backend::Memory => Ok(Self::Memory(MemoryStore::default())),
+            Backend::Database => Ok(Self::Database(database::DatabaseStore::new(
+                database.clone(),
+            ))),
+            Backend::Redis => {
+                let (pool, connection) = redis::connect(redis).await?;
+                Ok(Self::Redis {
+                    pool,
+                    connection: Mutex::new(Some(connection)),
+                    key_prefix: redis.key_prefix.clone(),
+                })
+            }
+        }
+    }
+
+    /// Close the connection to Redis. Returns an error if the connection failed.
+    pub async fn close(&self) -> Result<()> {
+        let SessionStore::Redis {
+            pool, connection, ..
+        } = self
+        else {
+            return Ok(());
+        };
+
+        pool.quit().await?;
+        let connection = connection.lock().unwrap().take();
+        if let Some(connection) = connection {
+            connection.await??;
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the session exists and is not expired.
+    pub async fn exists(&self, id: &str) -> Result<bool> {
+        let Ok(id) = id.parse::<Id>() else {
+            return Ok(false);
+        };
+        let record = match self {
+            SessionStore::Memory(store) => store.load(&id).await?,
+            SessionStore::Database(store) => store.load(&id).await?,
+            SessionStore::Redis {
+                pool, key_prefix, ..
+            } => {
+                redis::PrefixedStore::new(pool.clone(), key_prefix)
+                    .load(&id)
+                    .await?
+            }
+        };
+        Ok(record.is_some())
+    }
+
+    /// Delete a session, which logs out its user.
+    pub async fn delete(&self, id: &str) -> Result<()> {
+        let Ok(id) = id.parse::<Id>() else {
+            return Ok(());
+        };
+        match self {
+            SessionStore::Memory(store) => store.delete(&id).await?,
+            SessionStore::Database(store) => store.delete(&id).await?,
+            SessionStore::Redis {
+                pool, key_prefix, ..
+            } => {
+                redis::PrefixedStore::new(pool.clone(), key_prefix)
+                    .delete(&id)
+                    .await?
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add_layer<S: Clone + Send + Sync + 'static>(
+        &self,
+        environment: Environment,
+        policy: &policy::Config,
+        router: Router<S>,
+    ) -> Router<S> {
+        match self {
+            SessionStore::Memory(store) => router.layer(policy.layer(environment, store.clone())),
+            SessionStore::Database(store) => router.layer(policy.layer(environment, store.clone())),
+            SessionStore::Redis {
+                pool, key_prefix, ..
+            } => router.layer(policy.layer(
+                environment,
+                redis::PrefixedStore::new(pool.clone(), key_prefix),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use anyhow::Result;
+    use rstest::rstest;
+
+    use crate::test_helper::redis_container;
+
+    #[rstest]
+    #[tokio::test]
+    #[ignore = "manually only"]
+    async fn start_redis_container(
+        redis_container: impl Future<Output = Result<String>>,
+    ) -> Result<()> {
+        let _c = redis_container.await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+//! The lifetime of sessions and their cookies.
+//!
+//! Sessions expire after a period of inactivity, and regardless of activity after their absolute
+//! lifetime. Sessions logged in with "remember me" last until the remember-me duration elapsed.
+//! tower-sessions only expires inactive sessions, the absolute lifetime is enforced by the
+//! [`enforce`] middleware.
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tower_sessions::{
+    cookie::{self, time},
+    session_store::SessionStore,
+    Expiry, Session, SessionManagerLayer,
+};
+
+use super::Backend;
+use crate::{user::authenticated, Environment};
+
+const SESSION_START_KEY: &str = "session_start";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Where sessions are stored, memory in development and Redis in production by default.
+    pub store: Option<Backend>,
+    /// Seconds of inactivity after which a session expires.
+    pub inactivity_timeout: u64,
+    /// Seconds after the login after which a session expires, even if it is active.
+    pub absolute_lifetime: u64,
+    /// Seconds a session logged in with "remember me" lasts.
+    pub remember_me: u64,
+    pub cookie_name: String,
+    pub cookie_domain: Option<String>,
+    pub cookie_path: String,
+    pub same_site: SameSite,
+    /// Overrides if the cookie is sent over HTTPS only, which defaults to `true` in production.
+    pub secure: Option<bool>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            store: None,
+            inactivity_timeout: 30 * 60,
+            absolute_lifetime: 24 * 60 * 60,
+            remember_me: 30 * 24 * 60 * 60,
+            cookie_name: "id".into(),
+            cookie_domain: None,
+            cookie_path: "/".into(),
+            same_site: SameSite::Lax,
+            secure: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl From<SameSite> for cookie::SameSite {
+    fn from(same_site: SameSite) -> Self {
+        match same_site {
+            SameSite::Strict => Self::Strict,
+            SameSite::Lax => Self::Lax,
+            SameSite::None => Self::None,
+        }
+    }
+}
+
+impl Config {
+    /// The session layer for the store.
+    pub fn layer<Store: SessionStore + Clone>(
+        &self,
+        environment: Environment,
+        store: Store,
+    ) -> SessionManagerLayer<Store> {
+        let layer = SessionManagerLayer::new(store)
+            .with_name(self.cookie_name.clone())
+            .with_path(self.cookie_path.clone())
+            .with_same_site(self.same_site.into())
+            .with_secure(
+                self.secure
+                    .unwrap_or_else(|| environment.use_secure_cookies()),
+            )
+            .with_expiry(Expiry::OnInactivity(time::Duration::seconds(seconds(
+                self.inactivity_timeout,
+            ))));
+        match &self.cookie_domain {
+            Some(domain) => layer.with_domain(domain.clone()),
+            None => layer,
+        }
+    }
+
+    pub fn backend(&self, environment: Environment) -> Backend {
+        self.store
+            .unwrap_or_else(|| Backend::for_environment(environment))
+    }
+
+    fn lifetime(&self, start: &SessionStart) -> Duration {
+        Duration::seconds(seconds(if start.remember_me {
+            self.remember_me
+        } else {
+            self.absolute_lifetime
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SessionStart {
+    date: DateTime<Utc>,
+    remember_me: bool,
+}
+
+/// Start the lifetime of a session a user just logged in to.
+///
+/// A remembered session keeps its cookie across browser restarts until the remember-me duration
+/// elapsed, regardless of the inactivity timeout.
+pub async fn start(
+    session: &Session,
+    config: &Config,
+    remember_me: bool,
+    date: DateTime<Utc>,
+) -> Result<()> {
+    if remember_me {
+        let expiration = time::OffsetDateTime::from_unix_timestamp(date.timestamp())?
+            .checked_add(time::Duration::seconds(seconds(config.remember_me)))
+            .context("Remember-me duration out of range")?;
+        session.set_expiry(Some(Expiry::AtDateTime(expiration)));
+    }
+    session
+        .insert(SESSION_START_KEY, SessionStart { date, remember_me })
+        .await?;
+    Ok(())
+}
+
+/// Returns `true` if the session outlived its absolute lifetime.
+async fn expired(session: &Session, config: &Config, now: DateTime<Utc>) -> Result<bool> {
+    let Some(start) = session.get::<SessionStart>(SESSION_START_KEY).await? else {
+        // Sessions logged in before the lifetime was recorded start now.
+        if authenticated::user_id(session).await?.is_some() {
+            start(session, config, false, now).await?;
+        }
+        return Ok(false);
+    };
+    Ok(start.date + config.lifetime(&start) <= now)
+}
+
+/// Middleware that ends sessions that outlived their absolute lifetime before the request is
+/// handled.
+pub async fn enforce(
+    State(config): State<Config>,
+    session: Session,
+    request: Request,
+    next: Next,
+) -> Response {
+    match expired(&session, &config, Utc::now()).await {
+        Ok(false) => {}
+        Ok(true) => {
+            if let Err(e) = session.flush().await {
+                eprintln!("Failed to end expired session: {e:?}");
+            }
+        }
+        Err(e) => eprintln!("Failed to check session lifetime: {e:?}"),
+    }
+
+    next.run(request).await
+}
+
+fn seconds(seconds: u64) -> i64 {
+    seconds.try_into().unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{Duration, Utc};
+    use tower_sessions::{MemoryStore, Session};
+
+    use super::{expired, start, Config};
+
+    #[tokio::test]
+    async fn sessions_expire_after_their_absolute_lifetime() {
+        let config = Config {
+            absolute_lifetime: 60,
+            remember_me: 600,
+            ..Config::default()
+        };
+        let now = Utc::now();
+
+        let session = Session::new(None, Arc::new(MemoryStore::default()), None);
+        start(&session, &config, false, now).await.unwrap();
+        assert!(!expired(&session, &config, now + Duration::seconds(59))
+            .await
+            .unwrap());
+        assert!(expired(&session, &config, now + Duration::seconds(60))
+            .await
+            .unwrap());
+
+        let remembered = Session::new(None, Arc::new(MemoryStore::default()), None);
+        start(&remembered, &config, true, now).await.unwrap();
+        assert!(remembered.expiry().is_some());
+        assert!(!expired(&remembered, &config, now + Duration::seconds(60))
+            .await
+            .unwrap());
+        assert!(expired(&remembered, &config, now + Duration::seconds(600))
+            .await
+            .unwrap());
+    }
+}
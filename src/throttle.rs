@@ -0,0 +1,324 @@
+//! Expiring counters for throttling requests.
+//!
+//! Backed by the same backend as the sessions: the Redis pool of the session store in
+//! production, the `throttle` table with the database session store, and memory in development.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, Utc};
+use entity::throttle;
+use sea_orm::{prelude::*, sea_query::OnConflict, DatabaseBackend, DatabaseConnection, Statement};
+use tower_sessions_redis_store::fred::prelude::*;
+
+use crate::session::SessionStore;
+
+const KEY_PREFIX: &str = "throttle:";
+
+#[derive(Debug, Clone)]
+pub enum Throttle {
+    Memory(Arc<Mutex<HashMap<String, Counter>>>),
+    Database(DatabaseConnection),
+    Redis { pool: RedisPool, key_prefix: String },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Counter {
+    value: u64,
+    expires: Instant,
+}
+
+impl Throttle {
+    pub fn memory() -> Self {
+        Self::Memory(Default::default())
+    }
+
+    /// Use the same backend the sessions are stored in.
+    pub fn for_session_store(session_store: &SessionStore) -> Self {
+        match session_store {
+            SessionStore::Memory(_) => Self::memory(),
+            SessionStore::Database(store) => Self::Database(store.connection().clone()),
+            SessionStore::Redis {
+                pool, key_prefix, ..
+            } => Self::Redis {
+                pool: pool.clone(),
+                key_prefix: format!("{key_prefix}{KEY_PREFIX}"),
+            },
+        }
+    }
+
+    /// Increment a counter and return its new value.
+    ///
+    /// A counter expires `window` after it was incremented the first time.
+    pub async fn increment(&self, key: &str, window: Duration) -> Result<u64> {
+        match self {
+            Throttle::Memory(counters) => {
+                let now = Instant::now();
+                let mut counters = counters.lock().unwrap();
+                counters.retain(|_, counter| counter.expires > now);
+                let counter = counters.entry(key.into()).or_insert(Counter {
+                    value: 0,
+                    expires: now + window,
+                });
+                counter.value += 1;
+                Ok(counter.value)
+            }
+            Throttle::Database(connection) => {
+                let now = Utc::now();
+                // An expired counter starts over, in one statement so that concurrent
+                // increments are counted.
+                let statement = Statement::from_sql_and_values(
+                    DatabaseBackend::Postgres,
+                    r#"INSERT INTO "throttle" ("key", "value", "expiry_date") VALUES ($1, 1, $2)
+                       ON CONFLICT ("key") DO UPDATE SET
+                         "value" = CASE WHEN "throttle"."expiry_date" > $3
+                           THEN "throttle"."value" + 1 ELSE 1 END,
+                         "expiry_date" = CASE WHEN "throttle"."expiry_date" > $3
+                           THEN "throttle"."expiry_date" ELSE EXCLUDED."expiry_date" END
+                       RETURNING "value""#,
+                    [key.into(), expiry_date(now, window)?.into(), now.into()],
+                );
+                let row = connection
+                    .query_one(statement)
+                    .await?
+                    .context("The counter was not returned")?;
+                Ok(row.try_get::<i64>("", "value")? as u64)
+            }
+            Throttle::Redis { pool, key_prefix } => {
+                // Creating the counter with its expiration and incrementing it in one
+                // transaction, a separate `PEXPIRE` could be lost, and `PEXPIRE NX` requires
+                // Redis 7.
+                let key = redis_key(key_prefix, key);
+                let transaction = pool.multi();
+                transaction
+                    .set::<(), _, _>(
+                        &key,
+                        0,
+                        Some(Expiration::PX(millis(window))),
+                        Some(SetOptions::NX),
+                        false,
+                    )
+                    .await?;
+                transaction.incr::<(), _>(&key).await?;
+                let (_, value): (RedisValue, u64) = transaction.exec(true).await?;
+                Ok(value)
+            }
+        }
+    }
+
+    /// Set a counter to `value`, replacing its value and expiration.
+    pub async fn set(&self, key: &str, value: u64, expiration: Duration) -> Result<()> {
+        match self {
+            Throttle::Memory(counters) => {
+                counters.lock().unwrap().insert(
+                    key.into(),
+                    Counter {
+                        value,
+                        expires: Instant::now() + expiration,
+                    },
+                );
+            }
+            Throttle::Database(connection) => {
+                let counter = throttle::Model {
+                    key: key.into(),
+                    value: value.try_into().unwrap_or(i64::MAX),
+                    expiry_date: expiry_date(Utc::now(), expiration)?,
+                };
+                throttle::Entity::insert(throttle::ActiveModel::from(counter))
+                    .on_conflict(
+                        OnConflict::column(throttle::Column::Key)
+                            .update_columns([throttle::Column::Value, throttle::Column::ExpiryDate])
+                            .to_owned(),
+                    )
+                    .exec(connection)
+                    .await?;
+            }
+            Throttle::Redis { pool, key_prefix } => {
+                pool.set::<(), _, _>(
+                    redis_key(key_prefix, key),
+                    value,
+                    Some(Expiration::PX(millis(expiration))),
+                    None,
+                    false,
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The current value of a counter, `0` if it is not set or expired.
+    pub async fn get(&self, key: &str) -> Result<u64> {
+        match self {
+            Throttle::Memory(counters) => Ok(counters
+                .lock()
+                .unwrap()
+                .get(key)
+                .filter(|counter| counter.expires > Instant::now())
+                .map(|counter| counter.value)
+                .unwrap_or_default()),
+            Throttle::Database(connection) => Ok(find(connection, key)
+                .await?
+                .map(|counter| counter.value as u64)
+                .unwrap_or_default()),
+            Throttle::Redis { pool, key_prefix } => Ok(pool
+                .get::<Option<u64>, _>(redis_key(key_prefix, key))
+                .await?
+                .unwrap_or_default()),
+        }
+    }
+
+    /// The time until a counter expires, `None` if it is not set.
+    pub async fn time_to_live(&self, key: &str) -> Result<Option<Duration>> {
+        match self {
+            Throttle::Memory(counters) => Ok(counters
+                .lock()
+                .unwrap()
+                .get(key)
+                .and_then(|counter| counter.expires.checked_duration_since(Instant::now()))
+                .filter(|ttl| !ttl.is_zero())),
+            Throttle::Database(connection) => Ok(find(connection, key)
+                .await?
+                .and_then(|counter| {
+                    (counter.expiry_date.with_timezone(&Utc) - Utc::now())
+                        .to_std()
+                        .ok()
+                })
+                .filter(|ttl| !ttl.is_zero())),
+            Throttle::Redis { pool, key_prefix } => {
+                // Negative values signal a missing key or a key without expiration.
+                let ttl: i64 = pool.pttl(redis_key(key_prefix, key)).await?;
+                Ok((ttl > 0).then(|| Duration::from_millis(ttl as u64)))
+            }
+        }
+    }
+
+    pub async fn remove(&self, key: &str) -> Result<()> {
+        match self {
+            Throttle::Memory(counters) => {
+                counters.lock().unwrap().remove(key);
+            }
+            Throttle::Database(connection) => {
+                throttle::Entity::delete_by_id(key).exec(connection).await?;
+            }
+            Throttle::Redis { pool, key_prefix } => {
+                pool.del::<(), _>(redis_key(key_prefix, key)).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Delete the counters of the database backend that expired before `date`.
+pub async fn delete_expired(
+    connection: &DatabaseConnection,
+    date: DateTime<FixedOffset>,
+) -> Result<u64> {
+    let result = throttle::Entity::delete_many()
+        .filter(throttle::Column::ExpiryDate.lte(date))
+        .exec(connection)
+        .await?;
+    Ok(result.rows_affected)
+}
+
+/// The counter stored in the database, if it is not expired.
+async fn find(connection: &DatabaseConnection, key: &str) -> Result<Option<throttle::Model>> {
+    Ok(throttle::Entity::find_by_id(key)
+        .filter(throttle::Column::ExpiryDate.gt(Utc::now()))
+        .one(connection)
+        .await?)
+}
+
+fn expiry_date(now: DateTime<Utc>, duration: Duration) -> Result<DateTime<FixedOffset>> {
+    Ok((now + chrono::Duration::from_std(duration)?).into())
+}
+
+fn redis_key(prefix: &str, key: &str) -> String {
+    format!("{prefix}{key}")
+}
+
+fn millis(duration: Duration) -> i64 {
+    duration.as_millis().try_into().unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::Result;
+
+    use super::{delete_expired, Throttle, KEY_PREFIX};
+    use crate::test_helper;
+
+    #[tokio::test]
+    async fn memory_counters_expire() {
+        let throttle = Throttle::memory();
+        let window = Duration::from_millis(50);
+
+        assert_eq!(throttle.increment("a", window).await.unwrap(), 1);
+        assert_eq!(throttle.increment("a", window).await.unwrap(), 2);
+        assert_eq!(throttle.get("a").await.unwrap(), 2);
+        assert!(throttle.time_to_live("a").await.unwrap().is_some());
+
+        tokio::time::sleep(window + Duration::from_millis(10)).await;
+
+        assert_eq!(throttle.get("a").await.unwrap(), 0);
+        assert_eq!(throttle.time_to_live("a").await.unwrap(), None);
+        assert_eq!(throttle.increment("a", window).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Docker"]
+    async fn database_counters_expire() {
+        let connection = test_helper::database().await.unwrap();
+        let throttle = Throttle::Database(connection.clone());
+        let window = Duration::from_millis(500);
+
+        assert_eq!(throttle.increment("a", window).await.unwrap(), 1);
+        assert_eq!(throttle.increment("a", window).await.unwrap(), 2);
+        assert_eq!(throttle.get("a").await.unwrap(), 2);
+        assert!(throttle.time_to_live("a").await.unwrap().is_some());
+
+        tokio::time::sleep(window + Duration::from_millis(100)).await;
+
+        assert_eq!(throttle.get("a").await.unwrap(), 0);
+        assert_eq!(throttle.time_to_live("a").await.unwrap(), None);
+        assert_eq!(throttle.increment("a", window).await.unwrap(), 1);
+
+        throttle.set("b", 5, Duration::ZERO).await.unwrap();
+        assert_eq!(
+            delete_expired(&connection, chrono::Utc::now().into())
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(throttle.get("a").await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Docker"]
+    async fn redis_counters_expire() -> Result<()> {
+        let pool = test_helper::redis_pool(&Default::default()).await?;
+        let throttle = Throttle::Redis {
+            pool,
+            key_prefix: KEY_PREFIX.into(),
+        };
+        let window = Duration::from_millis(500);
+
+        assert_eq!(throttle.increment("a", window).await?, 1);
+        assert_eq!(throttle.increment("a", window).await?, 2);
+        assert_eq!(throttle.get("a").await?, 2);
+        assert!(throttle.time_to_live("a").await?.is_some());
+
+        tokio::time::sleep(window + Duration::from_millis(100)).await;
+
+        assert_eq!(throttle.get("a").await?, 0);
+        assert_eq!(throttle.time_to_live("a").await?, None);
+        assert_eq!(throttle.increment("a", window).await?, 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+//! Signed, expiring tokens for links sent out by email.
+
+use std::{
+    env,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims<T> {
+    #[serde(flatten)]
+    payload: T,
+    exp: u64,
+}
+
+/// Sign `payload` into a token that expires after `expiration`.
+pub fn sign(payload: impl Serialize, expiration: Duration) -> Result<String> {
+    let claims = Claims {
+        payload,
+        exp: expiration_timestamp(expiration),
+    };
+
+    Ok(encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_base64_secret(&secret()?)?,
+    )?)
+}
+
+/// Verify the signature and the expiration of a token and return its payload.
+pub fn verify<T: DeserializeOwned>(token: &str) -> Result<T> {
+    let claims = decode::<Claims<T>>(
+        token,
+        &DecodingKey::from_base64_secret(&secret()?)?,
+        &Validation::default(),
+    )?
+    .claims;
+    Ok(claims.payload)
+}
+
+fn secret() -> Result<String> {
+    env::var("JWT_SECRET").context("JWT_SECRET not set")
+}
+
+fn expiration_timestamp(expiration: Duration) -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+        + expiration.as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, time::Duration};
+
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Payload {
+        email: String,
+    }
+
+    #[test]
+    fn signed_token_roundtrips_and_rejects_tampering() {
+        env::set_var("JWT_SECRET", "c3RhY2stemVyby10ZXN0LXNlY3JldA==");
+
+        let payload = Payload {
+            email: "john@doe.com".into(),
+        };
+        let token = super::sign(&payload, Duration::from_secs(60)).unwrap();
+        assert_eq!(super::verify::<Payload>(&token).unwrap(), payload);
+
+        let tampered = format!("{token}x");
+        assert!(super::verify::<Payload>(&tampered).is_err());
+    }
+}
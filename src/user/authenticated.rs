@@ -0,0 +1,129 @@
+//! The user logged in to the current session.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use entity::user;
+use sea_orm::{prelude::*, DatabaseConnection};
+use tower_sessions::Session;
+
+use super::impersonation;
+use crate::{session::policy, AppError, StackZero};
+
+const USER_ID_KEY: &str = "user_id";
+
+/// Extracts the user logged in to the current session and rejects the request with
+/// `401 Unauthorized` if there is none.
+///
+/// While an admin impersonates a user, this is the impersonated user.
+#[derive(Debug)]
+pub struct Authenticated(pub user::Model);
+
+/// Like [`Authenticated`], but rejects the request with `403 Forbidden` while an admin
+/// impersonates the user. Used for sensitive actions.
+#[derive(Debug)]
+pub struct NotImpersonated(pub user::Model);
+
+/// Log in the user to the session.
+///
+/// The session id is cycled to prevent session fixation. A remembered session outlives the
+/// browser session, see [`policy::start`].
+pub async fn login(
+    session: &Session,
+    policy: &policy::Config,
+    user_id: Uuid,
+    remember_me: bool,
+) -> Result<()> {
+    session.cycle_id().await?;
+    impersonation::clear(session).await?;
+    session.insert(USER_ID_KEY, user_id).await?;
+    policy::start(session, policy, remember_me, Utc::now()).await
+}
+
+/// The id of the user logged in to the session, ignoring impersonations.
+pub async fn user_id(session: &Session) -> Result<Option<Uuid>> {
+    Ok(session.get::<Uuid>(USER_ID_KEY).await?)
+}
+
+/// Returns the current user and if the user is impersonated.
+pub async fn current(
+    session: &Session,
+    connection: &DatabaseConnection,
+) -> Result<Option<(user::Model, bool)>> {
+    let Some(user_id) = user_id(session).await? else {
+        return Ok(None);
+    };
+
+    let (user_id, impersonated) = match impersonation::active(session, connection).await? {
+        Some(impersonation) if impersonation.admin == user_id => (impersonation.user, true),
+        _ => (user_id, false),
+    };
+
+    Ok(user::Entity::find_by_id(user_id)
+        .one(connection)
+        .await?
+        .map(|user| (user, impersonated)))
+}
+
+async fn extract<S>(parts: &mut Parts, state: &S) -> Result<(user::Model, bool), Response>
+where
+    Arc<StackZero>: FromRef<S>,
+    S: Send + Sync,
+{
+    let session = Session::from_request_parts(parts, state)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let stack_zero = Arc::<StackZero>::from_ref(state);
+
+    match current(&session, &stack_zero.db_connection).await {
+        Ok(Some(current)) => Ok(current),
+        Ok(None) => Err(StatusCode::UNAUTHORIZED.into_response()),
+        Err(e) => Err(AppError::from(e).into_response()),
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Authenticated
+where
+    Arc<StackZero>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let (user, _) = extract(parts, state).await?;
+        Ok(Self(user))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for NotImpersonated
+where
+    Arc<StackZero>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match extract(parts, state).await? {
+            (user, false) => Ok(Self(user)),
+            (_, true) => Err(impersonation_forbidden()),
+        }
+    }
+}
+
+/// The response to sensitive actions while impersonating, see [`NotImpersonated`].
+pub fn impersonation_forbidden() -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        "Not allowed while impersonating a user",
+    )
+        .into_response()
+}
@@ -0,0 +1,251 @@
+//! Self-service account deletion.
+//!
+//! The user requests the deletion and confirms it with a link sent by email. The account is
+//! deleted after a grace period, in which the deletion can be canceled. Applications register
+//! [`Hook`]s to delete their own data of the user before the account is deleted.
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Query, State},
+    response::Response,
+};
+use chrono::{DateTime, FixedOffset};
+use entity::{invitation, user};
+use futures_util::future::BoxFuture;
+use sea_orm::{prelude::*, ActiveValue::Set, DatabaseConnection, TransactionTrait};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use url::Url;
+
+use super::users;
+use crate::{audit, email, respond, token, AppError, StackZero};
+
+// TODO: Add this to the configuration?
+const CONFIRMATION_EXPIRATION: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Days between the confirmation and the deletion of an account.
+    pub deletion_grace_period: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            deletion_grace_period: 14,
+        }
+    }
+}
+
+impl Config {
+    pub fn grace_period(&self) -> chrono::Duration {
+        chrono::Duration::days(self.deletion_grace_period.try_into().unwrap_or(i64::MAX))
+    }
+}
+
+/// Deletes the application's data of a user. Called before the account is deleted, if it
+/// fails, the account is not deleted and the deletion is retried later.
+pub type Hook =
+    Box<dyn Fn(DatabaseConnection, user::Model) -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+#[derive(Default)]
+pub struct Hooks(Vec<Hook>);
+
+impl Hooks {
+    pub fn push(&mut self, hook: Hook) {
+        self.0.push(hook);
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hooks({})", self.0.len())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    delete: Uuid,
+}
+
+/// The signed link to confirm the deletion.
+pub fn confirmation_link(base_url: &Url, user: &user::Model) -> Result<Url> {
+    let token = token::sign(Claims { delete: user.id }, CONFIRMATION_EXPIRATION)?;
+    let mut link = base_url.join("account/deletion/confirm")?;
+    link.query_pairs_mut().append_pair("t", &token);
+    Ok(link)
+}
+
+/// Render the email to confirm the deletion.
+pub fn render_email(state: &StackZero, user: &user::Model) -> Result<email::Content> {
+    let site = &state.config.base_url;
+    let link = confirmation_link(site, user)?;
+
+    state.render_email(
+        "emails/account_deletion",
+        user.locale.as_deref(),
+        json! {{
+            "site": site,
+            "name": user.name,
+            "email": user.email,
+            "link": link,
+            "grace_period_days": state.account_config.deletion_grace_period
+        }},
+    )
+}
+
+/// Schedule the deletion of the account after the grace period.
+pub async fn schedule(
+    connection: &DatabaseConnection,
+    config: &Config,
+    user: user::Model,
+    date: DateTime<FixedOffset>,
+) -> Result<user::Model> {
+    let deletion_date = date + config.grace_period();
+    let user_id = user.id;
+
+    let txn = connection.begin().await?;
+
+    let mut user = user::ActiveModel::from(user);
+    user.deletion_date = Set(Some(deletion_date));
+    let user = user.update(&txn).await?;
+
+    audit::record(
+        &txn,
+        Some(user_id),
+        Some(user_id),
+        "account.deletion-scheduled",
+        json! {{"deletion_date": deletion_date}},
+        date,
+    )
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(user)
+}
+
+pub async fn cancel(
+    connection: &DatabaseConnection,
+    user: user::Model,
+    date: DateTime<FixedOffset>,
+) -> Result<user::Model> {
+    let user_id = user.id;
+
+    let txn = connection.begin().await?;
+
+    let mut user = user::ActiveModel::from(user);
+    user.deletion_date = Set(None);
+    let user = user.update(&txn).await?;
+
+    audit::record(
+        &txn,
+        Some(user_id),
+        Some(user_id),
+        "account.deletion-canceled",
+        json! {{}},
+        date,
+    )
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(user)
+}
+
+/// Delete the account and everything related to it.
+///
+/// Memberships, identities and audit entries about the user are deleted by the database
+/// cascades.
+pub async fn delete(
+    connection: &DatabaseConnection,
+    hooks: &Hooks,
+    user: user::Model,
+    date: DateTime<FixedOffset>,
+) -> Result<()> {
+    for hook in &hooks.0 {
+        hook(connection.clone(), user.clone())
+            .await
+            .with_context(|| format!("Deletion hook failed for user {}", user.id))?;
+    }
+
+    let txn = connection.begin().await?;
+
+    invitation::Entity::delete_many()
+        .filter(users::email_matches(invitation::Column::Email, &user.email))
+        .exec(&txn)
+        .await?;
+
+    user::Entity::delete_by_id(user.id).exec(&txn).await?;
+
+    audit::record(
+        &txn,
+        None,
+        None,
+        "account.deleted",
+        json! {{"user_id": user.id}},
+        date,
+    )
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(())
+}
+
+/// Delete all accounts with an elapsed grace period. Returns the number of deleted accounts.
+pub async fn purge(
+    connection: &DatabaseConnection,
+    hooks: &Hooks,
+    date: DateTime<FixedOffset>,
+) -> Result<usize> {
+    let due = user::Entity::find()
+        .filter(user::Column::DeletionDate.lte(date))
+        .all(connection)
+        .await?;
+
+    let mut deleted = 0;
+    for user in due {
+        let user_id = user.id;
+        match delete(connection, hooks, user, date).await {
+            Ok(()) => deleted += 1,
+            Err(e) => eprintln!("Failed to delete user {user_id}: {e:?}"),
+        }
+    }
+
+    Ok(deleted)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmQuery {
+    t: String,
+}
+
+/// The handler for the link in the confirmation email.
+pub async fn confirm(
+    State(state): State<Arc<StackZero>>,
+    Query(query): Query<ConfirmQuery>,
+) -> Result<Response, AppError> {
+    let claims: Claims = token::verify(&query.t)?;
+    let connection = &state.db_connection;
+
+    let user = user::Entity::find_by_id(claims.delete)
+        .one(connection)
+        .await?
+        .context("User not found")?;
+
+    if user.deletion_date.is_none() {
+        schedule(
+            connection,
+            &state.account_config,
+            user,
+            chrono::Utc::now().into(),
+        )
+        .await?;
+    }
+
+    respond::redirect("/")
+}
@@ -0,0 +1,296 @@
+//! Changing the email address of a user.
+//!
+//! A verification link is sent to the new address and a notice to the old one. The address is
+//! only changed after the link was followed.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use entity::user;
+use sea_orm::{prelude::*, ActiveValue::Set, DatabaseConnection, SqlErr, TransactionTrait};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use url::Url;
+
+use super::{identities, users};
+use crate::{
+    email::{self, Address},
+    respond, token, AppError, StackZero,
+};
+
+// TODO: Add this to the configuration?
+const EMAIL_CHANGE_EXPIRATION: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    user: Uuid,
+    /// The address at the time the change was requested.
+    from: String,
+    to: Address,
+}
+
+#[derive(Debug)]
+pub enum Confirmation {
+    Changed,
+    /// The new address is already in use by another user.
+    AddressTaken,
+    /// The user's address changed since the change was requested, or the user does not exist
+    /// anymore.
+    Outdated,
+}
+
+/// The signed link to confirm the change.
+pub fn link(base_url: &Url, user: &user::Model, new_email: &Address) -> Result<Url> {
+    let token = token::sign(
+        Claims {
+            user: user.id,
+            from: user.email.clone(),
+            to: new_email.clone(),
+        },
+        EMAIL_CHANGE_EXPIRATION,
+    )?;
+    let mut link = base_url.join("email-change/confirm")?;
+    link.query_pairs_mut().append_pair("t", &token);
+    Ok(link)
+}
+
+/// Render the verification email to the new address and the notice to the old address.
+pub fn render_emails(
+    state: &StackZero,
+    user: &user::Model,
+    new_email: &Address,
+) -> Result<(email::Content, email::Content)> {
+    let site = &state.config.base_url;
+    let link = link(site, user, new_email)?;
+
+    let verification = state.render_email(
+        "emails/email_change_verification",
+        user.locale.as_deref(),
+        json! {{"site": site, "name": user.name, "email": new_email, "link": link}},
+    )?;
+    let notice = state.render_email(
+        "emails/email_change_notice",
+        user.locale.as_deref(),
+        json! {{"site": site, "name": user.name, "email": user.email, "new_email": new_email}},
+    )?;
+
+    Ok((verification, notice))
+}
+
+/// Whether `email` is the address of another user. Changing only the case of the user's own
+/// address is allowed.
+pub async fn address_taken(
+    connection: &DatabaseConnection,
+    user: &user::Model,
+    email: &Address,
+) -> Result<bool> {
+    Ok(user::Entity::find()
+        .filter(users::email_matches(user::Column::Email, email.as_str()))
+        .filter(user::Column::Id.ne(user.id))
+        .one(connection)
+        .await?
+        .is_some())
+}
+
+/// Change the user's address and the addresses of the linked identities.
+pub async fn change(
+    connection: &DatabaseConnection,
+    user_id: Uuid,
+    from: &str,
+    to: &Address,
+) -> Result<Confirmation> {
+    let txn = connection.begin().await?;
+
+    let Some(user) = user::Entity::find_by_id(user_id).one(&txn).await? else {
+        return Ok(Confirmation::Outdated);
+    };
+    if user.email != from {
+        return Ok(Confirmation::Outdated);
+    }
+
+    let mut user = user::ActiveModel::from(user);
+    user.email = Set(to.to_string());
+    if let Err(e) = user.update(&txn).await {
+        if let Some(SqlErr::UniqueConstraintViolation(_)) = e.sql_err() {
+            return Ok(Confirmation::AddressTaken);
+        }
+        return Err(e.into());
+    }
+
+    identities::update_email(&txn, user_id, to.as_str()).await?;
+
+    txn.commit().await?;
+
+    Ok(Confirmation::Changed)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmQuery {
+    t: String,
+}
+
+/// The handler for the link in the verification email.
+pub async fn confirm(
+    State(state): State<Arc<StackZero>>,
+    Query(query): Query<ConfirmQuery>,
+) -> Result<Response, AppError> {
+    let claims: Claims = token::verify(&query.t)?;
+
+    match change(&state.db_connection, claims.user, &claims.from, &claims.to).await? {
+        Confirmation::Changed => respond::redirect("/"),
+        Confirmation::AddressTaken => Ok((
+            StatusCode::CONFLICT,
+            "The email address is already in use by another account.",
+        )
+            .into_response()),
+        Confirmation::Outdated => Ok((
+            StatusCode::GONE,
+            "The email change link is not valid anymore.",
+        )
+            .into_response()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use chrono::Utc;
+    use entity::{identity, user};
+    use sea_orm::{prelude::*, DatabaseConnection};
+    use url::Url;
+
+    use super::{address_taken, change, link, Claims, Confirmation};
+    use crate::{email::Address, test_helper, token, user::identities};
+
+    fn address(email: &str) -> Address {
+        email.parse().unwrap()
+    }
+
+    async fn reload(connection: &DatabaseConnection, user: &user::Model) -> user::Model {
+        user::Entity::find_by_id(user.id)
+            .one(connection)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn links_carry_a_signed_token() {
+        env::set_var("JWT_SECRET", "c3RhY2stemVyby10ZXN0LXNlY3JldA==");
+
+        let user = user::Model {
+            id: Uuid::new_v4(),
+            name: "John".into(),
+            email: "john@doe.com".into(),
+            creation_date: Utc::now().into(),
+            password: String::new(),
+            deletion_date: None,
+            admin: false,
+            locale: None,
+        };
+        let base_url = Url::parse("https://example.com/").unwrap();
+        let link = link(&base_url, &user, &address("john@example.com")).unwrap();
+        assert_eq!(link.path(), "/email-change/confirm");
+
+        let (_, jwt) = link.query_pairs().find(|(name, _)| name == "t").unwrap();
+        let claims: Claims = token::verify(&jwt).unwrap();
+        assert_eq!(claims.user, user.id);
+        assert_eq!(claims.from, "john@doe.com");
+        assert_eq!(claims.to, address("john@example.com"));
+
+        let (header, rest) = jwt.split_once('.').unwrap();
+        let tampered = format!("{header}.{}", rest.replacen('e', "f", 1));
+        assert!(token::verify::<Claims>(&tampered).is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Docker"]
+    async fn confirmed_changes_update_the_user_and_identities() {
+        let connection = test_helper::database().await.unwrap();
+        let user = test_helper::user(&connection, "john@doe.com")
+            .await
+            .unwrap();
+        identities::link(
+            &connection,
+            user.id,
+            "auth0",
+            "auth0|john",
+            &user.email,
+            Utc::now().into(),
+        )
+        .await
+        .unwrap();
+
+        let to = address("john@example.com");
+        let confirmation = change(&connection, user.id, &user.email, &to)
+            .await
+            .unwrap();
+        assert!(matches!(confirmation, Confirmation::Changed));
+        assert_eq!(reload(&connection, &user).await.email, "john@example.com");
+        let identities = identity::Entity::find()
+            .filter(identity::Column::UserId.eq(user.id))
+            .all(&connection)
+            .await
+            .unwrap();
+        assert_eq!(identities[0].email, "john@example.com");
+
+        // The link can't be used again, the address it was sent for changed.
+        let confirmation = change(&connection, user.id, "john@doe.com", &to)
+            .await
+            .unwrap();
+        assert!(matches!(confirmation, Confirmation::Outdated));
+        let confirmation = change(&connection, Uuid::new_v4(), "john@doe.com", &to)
+            .await
+            .unwrap();
+        assert!(matches!(confirmation, Confirmation::Outdated));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Docker"]
+    async fn addresses_of_other_users_are_not_taken_over() {
+        let connection = test_helper::database().await.unwrap();
+        let user = test_helper::user(&connection, "john@doe.com")
+            .await
+            .unwrap();
+        test_helper::user(&connection, "Jane@Doe.com")
+            .await
+            .unwrap();
+
+        let confirmation = change(&connection, user.id, &user.email, &address("jane@doe.com"))
+            .await
+            .unwrap();
+
+        assert!(matches!(confirmation, Confirmation::AddressTaken));
+        assert_eq!(reload(&connection, &user).await.email, "john@doe.com");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Docker"]
+    async fn the_case_of_the_own_address_can_be_changed() {
+        let connection = test_helper::database().await.unwrap();
+        let user = test_helper::user(&connection, "John@doe.com")
+            .await
+            .unwrap();
+        test_helper::user(&connection, "jane@doe.com")
+            .await
+            .unwrap();
+
+        let new_email = address("john@doe.com");
+        assert!(!address_taken(&connection, &user, &new_email).await.unwrap());
+        assert!(address_taken(&connection, &user, &address("Jane@doe.com"))
+            .await
+            .unwrap());
+
+        let confirmation = change(&connection, user.id, &user.email, &new_email)
+            .await
+            .unwrap();
+        assert!(matches!(confirmation, Confirmation::Changed));
+        assert_eq!(reload(&connection, &user).await.email, "john@doe.com");
+    }
+}
@@ -0,0 +1,287 @@
+//! Export of all the data stored about a user.
+
+use std::io::{Cursor, Write};
+
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset};
+use entity::{
+    audit, email_outbox, identity, invitation, mailing_list, mailing_list_subscription,
+    organization, organization_member, organization_member::Role, user,
+};
+use sea_orm::{prelude::*, Condition, DatabaseConnection, QueryOrder};
+use serde::Serialize;
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+use super::{identities, users};
+use crate::session::index::{ActiveSession, Device};
+
+#[derive(Debug, Serialize)]
+pub struct Export {
+    pub account: Account,
+    pub identities: Vec<identity::Model>,
+    pub organizations: Vec<Membership>,
+    pub invitations: Vec<invitation::Model>,
+    pub sessions: Vec<SessionDevice>,
+    pub subscriptions: Vec<Subscription>,
+    pub emails: Vec<email_outbox::Model>,
+    pub audit: Vec<audit::Model>,
+}
+
+/// The user row without the password hash.
+#[derive(Debug, Serialize)]
+pub struct Account {
+    pub id: Uuid,
+    pub name: String,
+    pub email: String,
+    pub creation_date: DateTime<FixedOffset>,
+    pub has_password: bool,
+    pub deletion_date: Option<DateTime<FixedOffset>>,
+    pub locale: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Membership {
+    pub organization: organization::Model,
+    pub role: Role,
+    pub creation_date: DateTime<FixedOffset>,
+}
+
+/// An active session without its ID, the ID would allow to take the session over.
+#[derive(Debug, Serialize)]
+pub struct SessionDevice {
+    #[serde(flatten)]
+    pub device: Device,
+    pub current: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Subscription {
+    pub mailing_list: String,
+    pub title: String,
+    pub creation_date: DateTime<FixedOffset>,
+    pub unsubscribe_date: Option<DateTime<FixedOffset>>,
+}
+
+/// Collect everything that is stored about the user.
+///
+/// `sessions` are the user's active sessions, see [`crate::session::index::list`].
+pub async fn collect(
+    connection: &DatabaseConnection,
+    user: &user::Model,
+    sessions: Vec<ActiveSession>,
+) -> Result<Export> {
+    let account = Account {
+        id: user.id,
+        name: user.name.clone(),
+        email: user.email.clone(),
+        creation_date: user.creation_date,
+        has_password: !user.password.is_empty(),
+        deletion_date: user.deletion_date,
+        locale: user.locale.clone(),
+    };
+
+    let members = organization_member::Entity::find()
+        .filter(organization_member::Column::UserId.eq(user.id))
+        .all(connection)
+        .await?;
+    let organizations = organization::Entity::find()
+        .filter(organization::Column::Id.is_in(members.iter().map(|member| member.organization_id)))
+        .all(connection)
+        .await?;
+    let organizations = members
+        .into_iter()
+        .filter_map(|member| {
+            let organization = organizations
+                .iter()
+                .find(|organization| organization.id == member.organization_id)?;
+            Some(Membership {
+                organization: organization.clone(),
+                role: member.role,
+                creation_date: member.creation_date,
+            })
+        })
+        .collect();
+
+    let invitations = invitation::Entity::find()
+        .filter(users::email_matches(invitation::Column::Email, &user.email))
+        .all(connection)
+        .await?;
+
+    let sessions = sessions
+        .into_iter()
+        .map(|session| SessionDevice {
+            device: session.device,
+            current: session.current,
+        })
+        .collect();
+
+    let subscriptions = mailing_list_subscription::Entity::find()
+        .filter(mailing_list_subscription::Column::UserId.eq(user.id))
+        .all(connection)
+        .await?;
+    let mailing_lists = mailing_list::Entity::find()
+        .filter(
+            mailing_list::Column::Id.is_in(
+                subscriptions
+                    .iter()
+                    .map(|subscription| subscription.mailing_list_id),
+            ),
+        )
+        .all(connection)
+        .await?;
+    let subscriptions = subscriptions
+        .into_iter()
+        .filter_map(|subscription| {
+            let list = mailing_lists
+                .iter()
+                .find(|list| list.id == subscription.mailing_list_id)?;
+            Some(Subscription {
+                mailing_list: list.name.clone(),
+                title: list.title.clone(),
+                creation_date: subscription.creation_date,
+                unsubscribe_date: subscription.unsubscribe_date,
+            })
+        })
+        .collect();
+
+    let emails = email_outbox::Entity::find()
+        .filter(
+            Condition::any()
+                .add(users::email_matches(email_outbox::Column::To, &user.email))
+                .add(email_outbox::Column::UserId.eq(user.id)),
+        )
+        .order_by_asc(email_outbox::Column::CreationDate)
+        .all(connection)
+        .await?;
+
+    Ok(Export {
+        account,
+        identities: identities::of_user(connection, user.id).await?,
+        organizations,
+        invitations,
+        sessions,
+        subscriptions,
+        emails,
+        audit: crate::audit::of_user(connection, user.id).await?,
+    })
+}
+
+/// Create a ZIP archive with one JSON file per kind of data.
+pub fn zip(export: &Export) -> Result<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+
+    let mut add = |name: &str, json: Vec<u8>| -> Result<()> {
+        zip.start_file(name, options)?;
+        zip.write_all(&json)?;
+        Ok(())
+    };
+
+    add("account.json", serde_json::to_vec_pretty(&export.account)?)?;
+    add(
+        "identities.json",
+        serde_json::to_vec_pretty(&export.identities)?,
+    )?;
+    add(
+        "organizations.json",
+        serde_json::to_vec_pretty(&export.organizations)?,
+    )?;
+    add(
+        "invitations.json",
+        serde_json::to_vec_pretty(&export.invitations)?,
+    )?;
+    add(
+        "sessions.json",
+        serde_json::to_vec_pretty(&export.sessions)?,
+    )?;
+    add(
+        "subscriptions.json",
+        serde_json::to_vec_pretty(&export.subscriptions)?,
+    )?;
+    add("emails.json", serde_json::to_vec_pretty(&export.emails)?)?;
+    add("audit.json", serde_json::to_vec_pretty(&export.audit)?)?;
+
+    Ok(zip.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use chrono::Utc;
+    use sea_orm::prelude::Uuid;
+    use zip::ZipArchive;
+
+    use super::{Account, Export, SessionDevice};
+    use crate::session::index::Device;
+
+    #[test]
+    fn zip_contains_one_json_file_per_kind() {
+        let export = Export {
+            account: Account {
+                id: Uuid::new_v4(),
+                name: "John Doe".into(),
+                email: "john@doe.com".into(),
+                creation_date: Utc::now().into(),
+                has_password: true,
+                deletion_date: None,
+                locale: Some("de".into()),
+            },
+            identities: vec![],
+            organizations: vec![],
+            invitations: vec![],
+            sessions: vec![SessionDevice {
+                device: Device {
+                    user_agent: Some("Firefox".into()),
+                    ip: None,
+                    creation_date: Utc::now(),
+                    last_seen: Utc::now(),
+                },
+                current: true,
+            }],
+            subscriptions: vec![],
+            emails: vec![],
+            audit: vec![],
+        };
+
+        let zip = super::zip(&export).unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(zip)).unwrap();
+
+        let mut names: Vec<_> = archive.file_names().collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "account.json",
+                "audit.json",
+                "emails.json",
+                "identities.json",
+                "invitations.json",
+                "organizations.json",
+                "sessions.json",
+                "subscriptions.json"
+            ]
+        );
+
+        let mut account = String::new();
+        archive
+            .by_name("account.json")
+            .unwrap()
+            .read_to_string(&mut account)
+            .unwrap();
+        let account: serde_json::Value = serde_json::from_str(&account).unwrap();
+        assert_eq!(account["email"], "john@doe.com");
+        assert_eq!(account["locale"], "de");
+        assert!(account.get("password").is_none());
+
+        let mut sessions = String::new();
+        archive
+            .by_name("sessions.json")
+            .unwrap()
+            .read_to_string(&mut sessions)
+            .unwrap();
+        let sessions: serde_json::Value = serde_json::from_str(&sessions).unwrap();
+        assert_eq!(sessions[0]["user_agent"], "Firefox");
+        assert!(sessions[0].get("id").is_none());
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset};
+use entity::{identity, user};
+use sea_orm::{
+    prelude::*, sea_query::Expr, sea_query::OnConflict, ConnectionTrait, DatabaseConnection,
+};
+
+/// Link an external identity to a user. Does nothing if the identity is already linked.
+pub async fn link(
+    connection: &impl ConnectionTrait,
+    user_id: Uuid,
+    provider: &str,
+    subject: &str,
+    email: &str,
+    date: DateTime<FixedOffset>,
+) -> Result<()> {
+    let identity = identity::Model {
+        id: Uuid::new_v4(),
+        user_id,
+        provider: provider.into(),
+        subject: subject.into(),
+        email: email.into(),
+        creation_date: date,
+    };
+
+    identity::Entity::insert(identity::ActiveModel::from(identity))
+        .on_conflict(
+            OnConflict::columns([identity::Column::Provider, identity::Column::Subject])
+                .do_nothing()
+                .to_owned(),
+        )
+        .do_nothing()
+        .exec(connection)
+        .await?;
+
+    Ok(())
+}
+
+/// The user an external identity is linked to.
+pub async fn find_user(
+    connection: &DatabaseConnection,
+    provider: &str,
+    subject: &str,
+) -> Result<Option<user::Model>> {
+    let Some(identity) = identity::Entity::find()
+        .filter(identity::Column::Provider.eq(provider))
+        .filter(identity::Column::Subject.eq(subject))
+        .one(connection)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    Ok(user::Entity::find_by_id(identity.user_id)
+        .one(connection)
+        .await?)
+}
+
+/// Update the email of all the identities linked to the user.
+pub async fn update_email(
+    connection: &impl ConnectionTrait,
+    user_id: Uuid,
+    email: &str,
+) -> Result<()> {
+    identity::Entity::update_many()
+        .col_expr(identity::Column::Email, Expr::value(email))
+        .filter(identity::Column::UserId.eq(user_id))
+        .exec(connection)
+        .await?;
+    Ok(())
+}
+
+pub async fn of_user(
+    connection: &impl ConnectionTrait,
+    user_id: Uuid,
+) -> Result<Vec<identity::Model>> {
+    Ok(identity::Entity::find()
+        .filter(identity::Column::UserId.eq(user_id))
+        .all(connection)
+        .await?)
+}
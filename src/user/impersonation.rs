@@ -0,0 +1,316 @@
+//! Admins impersonating users to see what they see.
+//!
+//! The impersonation is stored in the session next to the admin's own login. It ends after a
+//! timeout, or when the admin exits it. Starting, stopping and expiring impersonations are
+//! written to the audit log.
+
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use axum::{extract::State, response::Response};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use entity::user;
+use sea_orm::{prelude::*, DatabaseConnection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tower_sessions::Session;
+
+use crate::{audit, respond, AppError, StackZero};
+
+const IMPERSONATION_KEY: &str = "impersonation";
+
+/// The path that ends the impersonation.
+pub const EXIT_PATH: &str = "/impersonation/exit";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Seconds until an impersonation ends automatically.
+    pub timeout: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self { timeout: 30 * 60 }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Impersonation {
+    pub admin: Uuid,
+    pub user: Uuid,
+    pub expiration_date: DateTime<Utc>,
+}
+
+/// The `impersonation` template context variable, used to show a banner while impersonating.
+#[derive(Debug, Serialize)]
+pub struct Banner {
+    pub admin: String,
+    pub user: String,
+    pub email: String,
+    pub expiration_date: DateTime<Utc>,
+    pub exit: &'static str,
+}
+
+/// Start impersonating `user`.
+pub async fn start(
+    session: &Session,
+    connection: &DatabaseConnection,
+    config: &Config,
+    admin: &user::Model,
+    user: &user::Model,
+    date: DateTime<FixedOffset>,
+) -> Result<Impersonation> {
+    if !admin.admin {
+        bail!("Only admins can impersonate users");
+    }
+    if user.admin {
+        bail!("Admins can not be impersonated");
+    }
+
+    let timeout = Duration::seconds(config.timeout.try_into().unwrap_or(i64::MAX));
+    let impersonation = Impersonation {
+        admin: admin.id,
+        user: user.id,
+        expiration_date: date.to_utc() + timeout,
+    };
+
+    audit::record(
+        connection,
+        Some(admin.id),
+        Some(user.id),
+        "impersonation.started",
+        json! {{"expiration_date": impersonation.expiration_date}},
+        date,
+    )
+    .await?;
+
+    session.insert(IMPERSONATION_KEY, &impersonation).await?;
+
+    Ok(impersonation)
+}
+
+/// Stop the impersonation, the admin is logged in as themselves again.
+pub async fn stop(
+    session: &Session,
+    connection: &DatabaseConnection,
+    date: DateTime<FixedOffset>,
+) -> Result<Option<Impersonation>> {
+    let Some(impersonation) = session.remove::<Impersonation>(IMPERSONATION_KEY).await? else {
+        return Ok(None);
+    };
+
+    audit::record(
+        connection,
+        Some(impersonation.admin),
+        Some(impersonation.user),
+        "impersonation.stopped",
+        json! {{}},
+        date,
+    )
+    .await?;
+
+    Ok(Some(impersonation))
+}
+
+/// The active impersonation. An expired impersonation is removed from the session.
+pub async fn active(
+    session: &Session,
+    connection: &DatabaseConnection,
+) -> Result<Option<Impersonation>> {
+    let Some(impersonation) = session.get::<Impersonation>(IMPERSONATION_KEY).await? else {
+        return Ok(None);
+    };
+
+    let now = Utc::now();
+    if impersonation.expiration_date > now {
+        return Ok(Some(impersonation));
+    }
+
+    session.remove_value(IMPERSONATION_KEY).await?;
+    audit::record(
+        connection,
+        Some(impersonation.admin),
+        Some(impersonation.user),
+        "impersonation.expired",
+        json! {{}},
+        now.into(),
+    )
+    .await?;
+
+    Ok(None)
+}
+
+/// Remove the impersonation from the session without recording it, used when the session is
+/// logged in to another user.
+pub async fn clear(session: &Session) -> Result<()> {
+    session.remove_value(IMPERSONATION_KEY).await?;
+    Ok(())
+}
+
+pub async fn banner(session: &Session, connection: &DatabaseConnection) -> Result<Option<Banner>> {
+    let Some(impersonation) = active(session, connection).await? else {
+        return Ok(None);
+    };
+
+    let admin = user::Entity::find_by_id(impersonation.admin)
+        .one(connection)
+        .await?;
+    let user = user::Entity::find_by_id(impersonation.user)
+        .one(connection)
+        .await?;
+    let (Some(admin), Some(user)) = (admin, user) else {
+        return Ok(None);
+    };
+
+    Ok(Some(Banner {
+        admin: admin.name,
+        user: user.name,
+        email: user.email,
+        expiration_date: impersonation.expiration_date,
+        exit: EXIT_PATH,
+    }))
+}
+
+/// The handler for the exit button in the banner.
+pub async fn exit(
+    State(state): State<Arc<StackZero>>,
+    session: Session,
+) -> Result<Response, AppError> {
+    stop(&session, &state.db_connection, Utc::now().into()).await?;
+    respond::redirect("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{Duration, Utc};
+    use entity::user;
+    use sea_orm::{ActiveModelTrait, ActiveValue::Set, DatabaseConnection};
+    use tower_sessions::{MemoryStore, Session};
+
+    use super::{active, banner, start, stop, Config, EXIT_PATH};
+    use crate::{audit, test_helper};
+
+    fn session() -> Session {
+        Session::new(None, Arc::new(MemoryStore::default()), None)
+    }
+
+    async fn user(connection: &DatabaseConnection, email: &str, admin: bool) -> user::Model {
+        let user = test_helper::user(connection, email).await.unwrap();
+        let mut user = user::ActiveModel::from(user);
+        user.admin = Set(admin);
+        user.update(connection).await.unwrap()
+    }
+
+    fn actions(audit: &[entity::audit::Model]) -> Vec<&str> {
+        audit.iter().map(|entry| entry.action.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn only_admins_can_impersonate_non_admins() {
+        let model = |admin| user::Model {
+            id: Default::default(),
+            name: String::new(),
+            email: String::new(),
+            creation_date: Utc::now().into(),
+            password: String::new(),
+            deletion_date: None,
+            admin,
+            locale: None,
+        };
+        // Rejected before the database is used.
+        let connection = DatabaseConnection::Disconnected;
+        let config = Config::default();
+        let session = session();
+
+        for (admin, user) in [(model(false), model(false)), (model(true), model(true))] {
+            assert!(start(
+                &session,
+                &connection,
+                &config,
+                &admin,
+                &user,
+                Utc::now().into()
+            )
+            .await
+            .is_err());
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Docker"]
+    async fn impersonation_starts_shows_a_banner_and_stops() {
+        let connection = test_helper::database().await.unwrap();
+        let admin = user(&connection, "admin@example.com", true).await;
+        let user = user(&connection, "user@example.com", false).await;
+        let session = session();
+        let now = Utc::now();
+
+        let impersonation = start(
+            &session,
+            &connection,
+            &Config::default(),
+            &admin,
+            &user,
+            now.into(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(impersonation.user, user.id);
+        assert_eq!(impersonation.expiration_date, now + Duration::minutes(30));
+        assert!(active(&session, &connection).await.unwrap().is_some());
+
+        let shown = banner(&session, &connection).await.unwrap().unwrap();
+        assert_eq!(shown.admin, "admin");
+        assert_eq!(shown.email, "user@example.com");
+        assert_eq!(shown.exit, EXIT_PATH);
+
+        let stopped = stop(&session, &connection, Utc::now().into())
+            .await
+            .unwrap();
+        assert_eq!(stopped.unwrap().admin, admin.id);
+        assert!(active(&session, &connection).await.unwrap().is_none());
+        assert!(banner(&session, &connection).await.unwrap().is_none());
+        assert!(stop(&session, &connection, Utc::now().into())
+            .await
+            .unwrap()
+            .is_none());
+
+        let audit = audit::of_user(&connection, user.id).await.unwrap();
+        assert_eq!(
+            actions(&audit),
+            ["impersonation.started", "impersonation.stopped"]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Docker"]
+    async fn impersonation_times_out() {
+        let connection = test_helper::database().await.unwrap();
+        let admin = user(&connection, "admin@example.com", true).await;
+        let user = user(&connection, "user@example.com", false).await;
+        let session = session();
+
+        start(
+            &session,
+            &connection,
+            &Config { timeout: 60 },
+            &admin,
+            &user,
+            (Utc::now() - Duration::minutes(2)).into(),
+        )
+        .await
+        .unwrap();
+
+        assert!(active(&session, &connection).await.unwrap().is_none());
+        assert!(banner(&session, &connection).await.unwrap().is_none());
+
+        let audit = audit::of_user(&connection, user.id).await.unwrap();
+        assert_eq!(
+            actions(&audit),
+            ["impersonation.started", "impersonation.expired"]
+        );
+    }
+}
@@ -0,0 +1,262 @@
+//! Brute-force protection for password logins.
+//!
+//! Failed attempts are counted per account and per IP address. Every failure delays the next
+//! attempt exponentially, and after `max_failures` failures the account is locked for
+//! `lockout` seconds. The user is notified by email and can unlock the account with a signed
+//! link.
+
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use axum::{
+    extract::{Query, State},
+    response::Response,
+};
+use entity::user;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use url::Url;
+
+use crate::{email, respond, throttle::Throttle, token, AppError, StackZero};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Failed attempts per account until the account gets locked.
+    pub max_failures: u64,
+    /// Failed attempts per IP address until further attempts from it are rejected.
+    pub max_ip_failures: u64,
+    /// Seconds the account stays locked and failures are remembered.
+    pub lockout: u64,
+    /// Seconds to wait after the first failure. Doubles with every further failure.
+    pub backoff: u64,
+    /// Maximum seconds to wait between two attempts.
+    pub max_backoff: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_failures: 10,
+            max_ip_failures: 100,
+            lockout: 15 * 60,
+            backoff: 1,
+            max_backoff: 60,
+        }
+    }
+}
+
+impl Config {
+    pub fn lockout_duration(&self) -> Duration {
+        Duration::from_secs(self.lockout)
+    }
+
+    /// The delay before the next attempt is allowed after `failures` failed attempts.
+    pub fn backoff_after(&self, failures: u64) -> Duration {
+        let exponent = failures.saturating_sub(1).min(63) as u32;
+        let backoff = self.backoff.saturating_mul(1u64 << exponent);
+        Duration::from_secs(backoff.min(self.max_backoff))
+    }
+}
+
+/// The throttled login attempts of an account from an IP address.
+pub struct Attempt<'a> {
+    throttle: &'a Throttle,
+    config: &'a Config,
+    account: String,
+    ip: Option<IpAddr>,
+}
+
+impl<'a> Attempt<'a> {
+    pub fn new(
+        throttle: &'a Throttle,
+        config: &'a Config,
+        email: &str,
+        ip: Option<IpAddr>,
+    ) -> Self {
+        Self {
+            throttle,
+            config,
+            account: email.to_lowercase(),
+            ip,
+        }
+    }
+
+    /// The time to wait until a login attempt is allowed, `None` if it is allowed now.
+    pub async fn retry_after(&self) -> Result<Option<Duration>> {
+        let mut keys = vec![self.locked_key(), self.account_backoff_key()];
+        if let Some(ip) = self.ip {
+            keys.push(ip_backoff_key(ip));
+            if self.throttle.get(&ip_failures_key(ip)).await? >= self.config.max_ip_failures {
+                keys.push(ip_failures_key(ip));
+            }
+        }
+
+        let mut retry_after = None;
+        for key in keys {
+            retry_after = retry_after.max(self.throttle.time_to_live(&key).await?);
+        }
+        Ok(retry_after)
+    }
+
+    /// Record a failed attempt. Returns `true` if the account got locked by it.
+    pub async fn failed(&self) -> Result<bool> {
+        let window = self.config.lockout_duration();
+
+        if let Some(ip) = self.ip {
+            let failures = self
+                .throttle
+                .increment(&ip_failures_key(ip), window)
+                .await?;
+            self.throttle
+                .set(&ip_backoff_key(ip), 1, self.config.backoff_after(failures))
+                .await?;
+        }
+
+        let failures = self
+            .throttle
+            .increment(&self.account_failures_key(), window)
+            .await?;
+        self.throttle
+            .set(
+                &self.account_backoff_key(),
+                1,
+                self.config.backoff_after(failures),
+            )
+            .await?;
+
+        if failures < self.config.max_failures {
+            return Ok(false);
+        }
+
+        self.throttle.set(&self.locked_key(), 1, window).await?;
+        self.throttle.remove(&self.account_failures_key()).await?;
+        Ok(true)
+    }
+
+    /// Record a successful login, resets the failures of the account.
+    pub async fn succeeded(&self) -> Result<()> {
+        self.throttle.remove(&self.account_failures_key()).await?;
+        self.throttle.remove(&self.account_backoff_key()).await
+    }
+
+    /// Unlock the account and forget its failures.
+    pub async fn unlock(&self) -> Result<()> {
+        self.throttle.remove(&self.locked_key()).await?;
+        self.succeeded().await
+    }
+
+    fn account_failures_key(&self) -> String {
+        format!("login:failures:account:{}", self.account)
+    }
+
+    fn account_backoff_key(&self) -> String {
+        format!("login:backoff:account:{}", self.account)
+    }
+
+    fn locked_key(&self) -> String {
+        format!("login:locked:{}", self.account)
+    }
+}
+
+fn ip_failures_key(ip: IpAddr) -> String {
+    format!("login:failures:ip:{ip}")
+}
+
+fn ip_backoff_key(ip: IpAddr) -> String {
+    format!("login:backoff:ip:{ip}")
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    unlock: String,
+}
+
+/// The signed link to unlock the account.
+pub fn unlock_link(base_url: &Url, config: &Config, email: &str) -> Result<Url> {
+    let token = token::sign(
+        Claims {
+            unlock: email.into(),
+        },
+        config.lockout_duration(),
+    )?;
+    let mut link = base_url.join("unlock")?;
+    link.query_pairs_mut().append_pair("t", &token);
+    Ok(link)
+}
+
+/// Render the email notifying the user about the lockout.
+pub fn render_email(state: &StackZero, user: &user::Model) -> Result<email::Content> {
+    let site = &state.config.base_url;
+    let config = &state.login_config;
+    let link = unlock_link(site, config, &user.email)?;
+
+    state.render_email(
+        "emails/account_locked",
+        user.locale.as_deref(),
+        json! {{
+            "site": site,
+            "name": user.name,
+            "email": user.email,
+            "link": link,
+            "lockout_minutes": config.lockout.div_ceil(60)
+        }},
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnlockQuery {
+    t: String,
+}
+
+/// The handler for the unlock link in the lockout email.
+pub async fn unlock(
+    State(state): State<Arc<StackZero>>,
+    Query(query): Query<UnlockQuery>,
+) -> Result<Response, AppError> {
+    let claims: Claims = token::verify(&query.t)?;
+    Attempt::new(&state.throttle, &state.login_config, &claims.unlock, None)
+        .unlock()
+        .await?;
+    respond::redirect("/login")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Attempt, Config};
+    use crate::throttle::Throttle;
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let config = Config::default();
+        assert_eq!(config.backoff_after(1), Duration::from_secs(1));
+        assert_eq!(config.backoff_after(2), Duration::from_secs(2));
+        assert_eq!(config.backoff_after(4), Duration::from_secs(8));
+        assert_eq!(config.backoff_after(100), Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn account_gets_locked_after_max_failures() {
+        let throttle = Throttle::memory();
+        let config = Config {
+            max_failures: 3,
+            ..Config::default()
+        };
+        let attempt = Attempt::new(&throttle, &config, "John@Doe.com", None);
+
+        assert!(attempt.retry_after().await.unwrap().is_none());
+        assert!(!attempt.failed().await.unwrap());
+        assert!(attempt.retry_after().await.unwrap().is_some());
+        assert!(!attempt.failed().await.unwrap());
+        assert!(attempt.failed().await.unwrap());
+
+        let locked = Attempt::new(&throttle, &config, "john@doe.com", None);
+        let retry_after = locked.retry_after().await.unwrap().unwrap();
+        assert!(retry_after > Duration::from_secs(config.max_backoff));
+
+        locked.unlock().await.unwrap();
+        assert!(locked.retry_after().await.unwrap().is_none());
+    }
+}
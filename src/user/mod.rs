@@ -1 +1,8 @@
+pub mod authenticated;
+pub mod deletion;
+pub mod email_change;
+pub mod export;
+pub mod identities;
+pub mod impersonation;
+pub mod lockout;
 pub mod users;
@@ -1,7 +1,13 @@
 use anyhow::Result;
 use chrono::{DateTime, FixedOffset};
 use entity::user;
-use sea_orm::{prelude::*, DatabaseConnection, EntityTrait, QueryFilter, TransactionTrait};
+use sea_orm::{
+    prelude::*,
+    sea_query::{Func, SimpleExpr},
+    DatabaseConnection, EntityTrait, QueryFilter, TransactionTrait,
+};
+
+use crate::email::Address;
 
 #[derive(Debug, Clone)]
 pub enum AuthenticationMethod {
@@ -13,8 +19,9 @@ pub enum AuthenticationMethod {
 pub async fn create(
     connection: &DatabaseConnection,
     name: &str,
-    email: &str,
+    email: &Address,
     authentication_method: AuthenticationMethod,
+    locale: Option<String>,
     date: DateTime<FixedOffset>,
 ) -> Result<user::Model> {
     let password = match authentication_method {
@@ -25,9 +32,12 @@ pub async fn create(
     let new_user = user::Model {
         id: Uuid::new_v4(),
         name: name.into(),
-        email: email.into(),
+        email: email.to_string(),
         creation_date: date,
         password,
+        deletion_date: None,
+        admin: false,
+        locale,
     };
 
     {
@@ -43,16 +53,34 @@ pub async fn create(
     Ok(new_user)
 }
 
+/// Verify the password of a user. Users signed up with single sign-on have no password.
+pub fn verify_password(user: &user::Model, password: &str) -> bool {
+    !user.password.is_empty() && password_auth::verify_password(password, &user.password).is_ok()
+}
+
+pub async fn get(connection: &DatabaseConnection, user_id: Uuid) -> Result<Option<user::Model>> {
+    Ok(user::Entity::find_by_id(user_id).one(connection).await?)
+}
+
+/// The user with the email address, compared case-insensitively.
 pub async fn get_by_email(
     connection: &DatabaseConnection,
-    user_email: &str,
+    user_email: &Address,
 ) -> Result<Option<user::Model>> {
     Ok(user::Entity::find()
-        .filter(user::Column::Email.eq(user_email))
+        .filter(email_matches(user::Column::Email, user_email.as_str()))
         .one(connection)
         .await?)
 }
 
+/// Compares the email address in `column` with `email` case-insensitively.
+///
+/// Addresses keep the case of their local part for delivery, but no two accounts may differ
+/// only in case, see the unique index on `LOWER(email)`.
+pub fn email_matches(column: impl ColumnTrait, email: &str) -> SimpleExpr {
+    Expr::expr(Func::lower(Expr::col(column))).eq(email.to_lowercase())
+}
+
 #[cfg(test)]
 mod tests {
     use std::{env, future::Future};
@@ -81,8 +109,9 @@ mod tests {
         let user = super::create(
             &_database,
             "John Doe",
-            "john@doe.com",
+            &"john@doe.com".parse()?,
             AuthenticationMethod::SingleSignOn,
+            None,
             Utc::now().into(),
         )
         .await?;
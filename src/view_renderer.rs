@@ -6,6 +6,8 @@ use anyhow::{anyhow, bail, Result};
 use serde::Serialize;
 use tera::{Context, Tera};
 
+use crate::email;
+
 #[derive(Debug)]
 pub struct ViewRenderer {
     tera: Tera,
@@ -13,15 +15,32 @@ pub struct ViewRenderer {
 
 impl ViewRenderer {
     pub fn from_dir(dir: &Path) -> Result<ViewRenderer> {
-        let tera = Tera::new(
+        let mut tera = Tera::new(
             dir.join("**")
-                .join("*.html")
+                .join("*.{html,txt}")
                 .to_str()
                 .ok_or_else(|| anyhow!("Invalid path glob"))?,
         )?;
+        tera.register_function("cid", email::attachment::cid_function);
         Ok(Self { tera })
     }
 
+    /// Inline the CSS of the email templates, see [`email::css`]. Returns warnings about CSS
+    /// email clients don't support.
+    pub fn precompile_emails(&mut self, assets: &Path) -> Result<Vec<String>> {
+        email::css::precompile(&mut self.tera, assets)
+    }
+
+    /// The names of all templates.
+    pub fn template_names(&self) -> impl Iterator<Item = &str> {
+        self.tera.get_template_names()
+    }
+
+    /// Is there a template named `key`?
+    pub fn contains(&self, key: &str) -> bool {
+        self.template_names().any(|name| name == key)
+    }
+
     pub fn render(&self, key: &str, data: impl Serialize) -> Result<String> {
         let context = Context::from_serialize(data)?;
 